@@ -0,0 +1,255 @@
+use filetime::{self, FileTime};
+use std::fs::Metadata;
+use std::io::{self, Read};
+use std::path::Path;
+use tar::{Entry, EntryType, Header, HeaderMode};
+
+const PAX_MTIME: &str = "mtime";
+const PAX_XATTR: &str = "SCHILY.xattr.";
+
+///
+/// Attributes of files recorded on packing and restored on unpacking.
+///
+/// Build tools such as cargo and make compare mtimes to find outdated outputs,
+/// so mtimes are preserved in nanoseconds by PAX headers by default.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attributes {
+    /// Modification times in nanoseconds.
+    pub mtime: bool,
+    /// All bits of modes including setuid, setgid and sticky bits.
+    /// Otherwise only the bits of read, write and execute are recorded and restored.
+    pub permissions: bool,
+    /// Numeric IDs of the owner and the group, which are restored by privileged users.
+    pub ownership: bool,
+    /// Extended attributes of files.
+    pub xattrs: bool,
+    /// Recorded instead of mtimes of files (in seconds since the epoch),
+    /// which makes tarballs of the same files identical.
+    pub normalized_mtime: Option<u64>,
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Attributes {
+            mtime: true,
+            permissions: false,
+            ownership: false,
+            xattrs: false,
+            normalized_mtime: None,
+        }
+    }
+}
+
+impl Attributes {
+    ///
+    /// The header of the file and the PAX records of what the header can't hold.
+    ///
+    pub fn header(&self, path: &Path, meta: &Metadata) -> io::Result<(Header, Vec<u8>)> {
+        let mut header = Header::new_gnu();
+        let mut records = Vec::new();
+
+        header.set_metadata_in_mode(meta, HeaderMode::Complete);
+
+        match self.normalized_mtime {
+            Some(mtime) => header.set_mtime(mtime),
+            None if self.mtime => {
+                let mtime = FileTime::from_last_modification_time(meta);
+                let seconds = mtime.unix_seconds().max(0);
+
+                header.set_mtime(seconds as u64);
+                records.extend(record(
+                    PAX_MTIME,
+                    format!("{}.{:09}", seconds, mtime.nanoseconds()).as_bytes(),
+                ));
+            }
+            None => header.set_mtime(0),
+        }
+
+        if !self.permissions {
+            let mode = header.mode()?;
+            header.set_mode(mode & 0o777);
+        }
+
+        if !self.ownership {
+            header.set_uid(0);
+            header.set_gid(0);
+        }
+
+        if self.xattrs {
+            records.extend(xattrs(path)?);
+        }
+
+        Ok((header, records))
+    }
+
+    ///
+    /// Restore the ownership and the mtime of the file unpacked from the entry.
+    /// Mtimes of directories are returned instead, since unpacking files into them changes their mtimes.
+    ///
+    pub fn restore<R: Read>(
+        &self,
+        entry: &mut Entry<R>,
+        dst: &Path,
+    ) -> io::Result<Option<FileTime>> {
+        let kind = entry.header().entry_type();
+
+        if self.ownership {
+            chown(dst, entry.header())?;
+        }
+
+        if !self.mtime || kind.is_hard_link() {
+            return Ok(None);
+        }
+
+        let mtime = mtime_of(entry)?;
+
+        if kind.is_dir() {
+            return Ok(Some(mtime));
+        }
+
+        if kind.is_symlink() {
+            filetime::set_symlink_file_times(dst, mtime, mtime)?;
+        } else if kind == EntryType::Regular || kind == EntryType::Continuous {
+            filetime::set_file_times(dst, mtime, mtime)?;
+        }
+
+        Ok(None)
+    }
+}
+
+// The mtime in the PAX header falls back to the one in seconds in the header.
+fn mtime_of<R: Read>(entry: &mut Entry<R>) -> io::Result<FileTime> {
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+
+            if extension.key() == Ok(PAX_MTIME) {
+                if let Some(mtime) = extension.value().ok().and_then(parse_mtime) {
+                    return Ok(mtime);
+                }
+            }
+        }
+    }
+
+    Ok(FileTime::from_unix_time(entry.header().mtime()? as i64, 0))
+}
+
+fn parse_mtime(value: &str) -> Option<FileTime> {
+    let mut parts = value.splitn(2, '.');
+    let seconds = parts.next()?.parse().ok()?;
+    let nanos = match parts.next() {
+        // Fractions are padded or truncated to nanoseconds.
+        Some(fraction) => format!("{:0<9.9}", fraction).parse().ok()?,
+        None => 0,
+    };
+
+    Some(FileTime::from_unix_time(seconds, nanos))
+}
+
+// A record is "<length> <key>=<value>\n", where the length includes its own digits.
+fn record(key: &str, value: &[u8]) -> Vec<u8> {
+    let len = key.len() + value.len() + 3;
+    let mut total = len + len.to_string().len();
+
+    if total.to_string().len() > len.to_string().len() {
+        total += 1;
+    }
+
+    let mut record = format!("{} {}=", total, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+#[cfg(unix)]
+fn xattrs(path: &Path) -> io::Result<Vec<u8>> {
+    let mut records = Vec::new();
+
+    for name in xattr::list(path)? {
+        if let Some(value) = xattr::get(path, &name)? {
+            records.extend(record(
+                &format!("{}{}", PAX_XATTR, name.to_string_lossy()),
+                &value,
+            ));
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(not(unix))]
+fn xattrs(_path: &Path) -> io::Result<Vec<u8>> {
+    Ok(Vec::new())
+}
+
+// Others can't give files away, so the ownership is left to the user unpacking them.
+#[cfg(unix)]
+fn chown(dst: &Path, header: &Header) -> io::Result<()> {
+    use std::os::unix::fs::lchown;
+
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+
+    lchown(dst, Some(header.uid()? as u32), Some(header.gid()? as u32))
+}
+
+#[cfg(not(unix))]
+fn chown(_dst: &Path, _header: &Header) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile;
+
+    #[test]
+    fn records_count_their_own_length() {
+        assert_eq!(record("mtime", b"1"), b"11 mtime=1\n".to_vec());
+        assert_eq!(record("mtime", b"1234567"), b"17 mtime=1234567\n".to_vec());
+        assert_eq!(
+            record("mtime", b"12345678"),
+            b"18 mtime=12345678\n".to_vec()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes_are_recorded_without_special_bits_by_default() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "famo").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o4750)).unwrap();
+        let meta = fs::metadata(&path).unwrap();
+
+        let (header, _) = Attributes::default().header(&path, &meta).unwrap();
+        assert_eq!(header.mode().unwrap(), 0o750);
+
+        let permissions = Attributes {
+            permissions: true,
+            ..Attributes::default()
+        };
+        let (header, _) = permissions.header(&path, &meta).unwrap();
+        assert_eq!(header.mode().unwrap() & 0o7777, 0o4750);
+    }
+
+    #[test]
+    fn fractions_of_mtimes_are_nanoseconds() {
+        let mtime = parse_mtime("1546300800.5").unwrap();
+        assert_eq!(
+            (mtime.unix_seconds(), mtime.nanoseconds()),
+            (1546300800, 500_000_000)
+        );
+
+        let mtime = parse_mtime("1546300800.1234567891").unwrap();
+        assert_eq!(mtime.nanoseconds(), 123_456_789);
+
+        assert_eq!(parse_mtime("1546300800").unwrap().nanoseconds(), 0);
+        assert!(parse_mtime("now").is_none());
+    }
+}
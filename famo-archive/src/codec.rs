@@ -0,0 +1,238 @@
+use failure::Error;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+use std::str::FromStr;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use zstd;
+use Options;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+///
+/// Compression of tarballs. Each of them is detected by the magic number at the head of the stream.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Codec {
+    #[default]
+    Gzip,
+    Zstd,
+    Xz,
+    /// Tarballs as they are, which have no magic number at the head.
+    None,
+}
+
+impl Codec {
+    ///
+    /// The codec of the stream starting with `head`.
+    ///
+    pub fn detect(head: &[u8]) -> Codec {
+        if head.starts_with(GZIP_MAGIC) {
+            Codec::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Codec::Zstd
+        } else if head.starts_with(XZ_MAGIC) {
+            Codec::Xz
+        } else {
+            Codec::None
+        }
+    }
+
+    ///
+    /// The lowest and the highest levels. Higher levels compress smaller and slower.
+    ///
+    pub fn levels(self) -> (i32, i32) {
+        match self {
+            Codec::Gzip | Codec::Xz => (0, 9),
+            Codec::Zstd => (1, 22),
+            Codec::None => (0, 0),
+        }
+    }
+
+    pub fn default_level(self) -> i32 {
+        match self {
+            Codec::Gzip | Codec::Xz => 6,
+            Codec::Zstd => 3,
+            Codec::None => 0,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            "xz" => Ok(Codec::Xz),
+            "none" => Ok(Codec::None),
+            _ => Err(format_err!("Unknown compression '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+            Codec::None => "none",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+///
+/// A writer which compresses everything written into the inner writer by the codec.
+/// `finish` must be called to flush the end of the stream.
+///
+pub enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+    None(W),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(w: W, options: &Options) -> Result<Self, Error> {
+        options.validate()?;
+
+        let level = options.level();
+
+        let encoder = match options.codec {
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(w, Compression::new(level as u32))),
+            Codec::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(w, level)?;
+
+                // Frames are compressed by the calling thread alone unless workers are given.
+                if options.threads > 1 {
+                    encoder.multithread(options.threads)?;
+                }
+
+                Encoder::Zstd(encoder)
+            }
+            Codec::Xz => Encoder::Xz(XzEncoder::new(w, level as u32)),
+            Codec::None => Encoder::None(w),
+        };
+
+        Ok(encoder)
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        let w = match self {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::Xz(encoder) => encoder.finish()?,
+            Encoder::None(w) => w,
+        };
+
+        Ok(w)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::None(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::None(w) => w.flush(),
+        }
+    }
+}
+
+///
+/// A reader which decompresses the stream by the codec detected from its head,
+/// whichever codec the caller prefers for compression.
+///
+pub fn decoder<'a, R>(mut r: R) -> Result<Box<dyn Read + 'a>, Error>
+where
+    R: Read + 'a,
+{
+    let mut head = Vec::with_capacity(XZ_MAGIC.len());
+    (&mut r)
+        .take(XZ_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+
+    let codec = Codec::detect(&head);
+    let r = Cursor::new(head).chain(r);
+
+    let decoder: Box<dyn Read + 'a> = match codec {
+        Codec::Gzip => Box::new(GzDecoder::new(r)),
+        Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(r)?),
+        Codec::Xz => Box::new(XzDecoder::new(r)),
+        Codec::None => Box::new(r),
+    };
+
+    Ok(decoder)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CODECS: &[Codec] = &[Codec::Gzip, Codec::Zstd, Codec::Xz, Codec::None];
+
+    fn encode(data: &[u8], options: &Options) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn every_codec_is_detected_on_decoding() {
+        let data = b"famo ".repeat(1024);
+
+        for codec in CODECS {
+            for threads in &[1, 4] {
+                let options = Options {
+                    codec: *codec,
+                    threads: *threads,
+                    ..Options::default()
+                };
+                let encoded = encode(&data, &options);
+                let mut decoded = vec![];
+
+                decoder(encoded.as_slice())
+                    .unwrap()
+                    .read_to_end(&mut decoded)
+                    .unwrap();
+
+                assert_eq!(Codec::detect(&encoded), *codec);
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn levels_are_validated_by_codec() {
+        let mut options = Options::default();
+        assert_eq!(options.level(), 6);
+
+        options.codec = Codec::Zstd;
+        options.level = Some(19);
+        assert!(options.validate().is_ok());
+
+        options.codec = "xz".parse().unwrap();
+        assert!(options.validate().is_err());
+        assert!(Encoder::new(Vec::new(), &options).is_err());
+        assert!("lz4".parse::<Codec>().is_err());
+    }
+}
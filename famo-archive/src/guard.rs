@@ -0,0 +1,329 @@
+use failure::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tar::{Entry, EntryType};
+use ArchiveError;
+
+// Symlinks followed to resolve a path at most, as many as Linux follows.
+const MAX_HOPS: usize = 40;
+
+///
+/// Limits of tarballs unpacked, which refuse tarballs expanding enormously. (a.k.a. tar bombs)
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Total bytes of files unpacked.
+    pub max_size: u64,
+    pub max_entries: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_size: 32 * 1024 * 1024 * 1024,
+            max_entries: 1_000_000,
+        }
+    }
+}
+
+///
+/// Validate every entry before it's unpacked into the destination,
+/// since caches come back from storages shared with others.
+///
+/// Paths are resolved against the destination on the disk, following symlinks unpacked before.
+/// The destination must hold nothing but the entries unpacked from the tarball.
+///
+pub struct Guard<'a> {
+    dist: PathBuf,
+    limits: &'a Limits,
+    size: u64,
+    entries: u64,
+    symlinks: Vec<PathBuf>,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(dist: &Path, limits: &'a Limits) -> Result<Self, Error> {
+        fs::create_dir_all(dist)?;
+
+        Ok(Guard {
+            dist: dist.to_path_buf(),
+            limits,
+            size: 0,
+            entries: 0,
+            symlinks: vec![],
+        })
+    }
+
+    pub fn check<R: Read>(&mut self, entry: &Entry<R>) -> Result<(), Error> {
+        let path = entry.path()?.into_owned();
+        let unsafe_entry = |reason: &str| -> Error {
+            ArchiveError::Unsafe {
+                path: path.display().to_string(),
+                reason: reason.to_owned(),
+            }
+            .into()
+        };
+
+        self.entries += 1;
+
+        if self.entries > self.limits.max_entries {
+            return Err(ArchiveError::TooManyEntries {
+                path: path.display().to_string(),
+                limit: self.limits.max_entries,
+            }
+            .into());
+        }
+
+        let parts = relative(&path).map_err(&unsafe_entry)?;
+        let parent = self
+            .resolve(
+                vec![],
+                &join(&parts[..parts.len().saturating_sub(1)]),
+                &mut 0,
+            )
+            .ok_or_else(|| unsafe_entry("the parent is outside of the destination"))?;
+        let kind = entry.header().entry_type();
+
+        match kind {
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                let size = entry.header().size()?;
+                let limit = self.limits.max_size;
+                let too_large = || ArchiveError::TooLarge {
+                    path: path.display().to_string(),
+                    limit,
+                };
+
+                // Sizes in headers are anything planted, so the sum of them may overflow.
+                self.size = self.size.checked_add(size).ok_or_else(&too_large)?;
+
+                if self.size > limit {
+                    return Err(too_large().into());
+                }
+            }
+            EntryType::Directory => {}
+            EntryType::Symlink | EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| unsafe_entry("the link has no target"))?;
+
+                if absolute(&target) {
+                    return Err(unsafe_entry(&format!(
+                        "the link to the absolute path '{}'",
+                        target.display()
+                    )));
+                }
+
+                // Symlinks are relative to their parents, and hard links are relative to the destination.
+                let base = if kind == EntryType::Symlink {
+                    parent
+                } else {
+                    vec![]
+                };
+
+                if self.resolve(base, &target, &mut 0).is_none() {
+                    return Err(unsafe_entry(&format!(
+                        "the link to '{}' escapes the destination",
+                        target.display()
+                    )));
+                }
+
+                if kind == EntryType::Symlink {
+                    self.symlinks.push(path.clone());
+                }
+            }
+            _ => return Err(unsafe_entry("the type of the entry is not supported")),
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Verify symlinks again after all entries are unpacked,
+    /// since symlinks unpacked later change where the ones unpacked before point to.
+    ///
+    pub fn verify(&self) -> Result<(), Error> {
+        for path in &self.symlinks {
+            if self.resolve(vec![], path, &mut 0).is_none() {
+                return Err(ArchiveError::Unsafe {
+                    path: path.display().to_string(),
+                    reason: "the link escapes the destination through links unpacked later"
+                        .to_owned(),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Names of the target under the destination as it's resolved on the disk from the base.
+    // Paths which don't exist yet are resolved lexically, since unpacking creates them as they are.
+    fn resolve(
+        &self,
+        mut base: Vec<OsString>,
+        target: &Path,
+        hops: &mut usize,
+    ) -> Option<Vec<OsString>> {
+        for component in target.components() {
+            match component {
+                Component::Normal(part) => {
+                    base.push(part.to_owned());
+
+                    let path = self.dist.join(join(&base));
+                    let symlink = fs::symlink_metadata(&path)
+                        .map(|meta| meta.file_type().is_symlink())
+                        .unwrap_or(false);
+
+                    if !symlink {
+                        continue;
+                    }
+
+                    let link = fs::read_link(&path).ok()?;
+                    *hops += 1;
+
+                    if *hops > MAX_HOPS || absolute(&link) {
+                        return None;
+                    }
+
+                    base.pop();
+                    base = self.resolve(base, &link, hops)?;
+                }
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    base.pop()?;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(base)
+    }
+}
+
+fn absolute(path: &Path) -> bool {
+    path.has_root() || matches!(path.components().next(), Some(Component::Prefix(_)))
+}
+
+// Names of the path under the destination. Paths escaping the destination are refused
+// instead of being sanitized, since they never appear in caches packed by famo.
+fn relative(path: &Path) -> Result<Vec<OsString>, &'static str> {
+    if absolute(path) {
+        return Err("the absolute path");
+    }
+
+    let mut parts = vec![];
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_owned()),
+            Component::CurDir => {}
+            _ => return Err("the path traversal by '..'"),
+        }
+    }
+
+    Ok(parts)
+}
+
+fn join(parts: &[OsString]) -> PathBuf {
+    parts.iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile;
+
+    fn parts(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn paths_escaping_the_destination_are_refused() {
+        assert_eq!(
+            relative(Path::new("./target/debug")).unwrap(),
+            parts(&["target", "debug"])
+        );
+        assert!(relative(Path::new("/etc/passwd")).is_err());
+        assert!(relative(Path::new("target/../../.ssh/authorized_keys")).is_err());
+    }
+
+    #[test]
+    fn links_are_resolved_from_their_bases() {
+        let dist = tempfile::tempdir().unwrap();
+        let limits = Limits::default();
+        let guard = Guard::new(dist.path(), &limits).unwrap();
+
+        assert_eq!(
+            guard.resolve(
+                parts(&["target", "debug"]),
+                Path::new("../release/famo"),
+                &mut 0
+            ),
+            Some(parts(&["target", "release", "famo"]))
+        );
+        assert_eq!(
+            guard.resolve(parts(&["target"]), Path::new(".."), &mut 0),
+            Some(vec![])
+        );
+        assert!(guard
+            .resolve(parts(&["target"]), Path::new("../.."), &mut 0)
+            .is_none());
+        assert!(guard
+            .resolve(vec![], Path::new("a/../../b"), &mut 0)
+            .is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_on_the_disk_are_followed() {
+        use std::os::unix::fs::symlink;
+
+        let dist = tempfile::tempdir().unwrap();
+        let limits = Limits::default();
+        let guard = Guard::new(dist.path(), &limits).unwrap();
+
+        fs::create_dir(dist.path().join("b")).unwrap();
+        symlink("..", dist.path().join("b").join("c")).unwrap();
+        symlink("loop", dist.path().join("loop")).unwrap();
+
+        assert_eq!(
+            guard.resolve(vec![], Path::new("b/c/b"), &mut 0),
+            Some(parts(&["b"]))
+        );
+        assert!(guard.resolve(vec![], Path::new("b/c/.."), &mut 0).is_none());
+        assert!(guard.resolve(vec![], Path::new("loop"), &mut 0).is_none());
+    }
+
+    #[test]
+    fn sizes_overflowing_are_too_large() {
+        let dist = tempfile::tempdir().unwrap();
+        let limits = Limits {
+            max_size: u64::MAX,
+            ..Limits::default()
+        };
+        let mut guard = Guard::new(dist.path(), &limits).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("famo").unwrap();
+        header.set_size(4);
+        header.set_cksum();
+        builder.append(&header, &b"famo"[..]).unwrap();
+
+        let tarball = builder.into_inner().unwrap();
+        let mut archive = tar::Archive::new(tarball.as_slice());
+        let entry = archive.entries().unwrap().next().unwrap().unwrap();
+
+        guard.size = u64::MAX - 3;
+
+        match guard.check(&entry).unwrap_err().downcast().unwrap() {
+            ArchiveError::TooLarge { path, limit } => {
+                assert_eq!(path, "famo");
+                assert_eq!(limit, u64::MAX);
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+}
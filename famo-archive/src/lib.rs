@@ -1,28 +1,177 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
 extern crate failure;
+extern crate filetime;
 extern crate flate2;
+#[cfg(unix)]
+extern crate libc;
 extern crate tar;
+extern crate tempfile;
+#[cfg(unix)]
+extern crate xattr;
+extern crate xz2;
+extern crate zstd;
+
+pub mod attributes;
+pub mod codec;
+pub mod guard;
+
+pub use attributes::Attributes;
+pub use codec::Codec;
+pub use guard::Limits;
 
+use codec::Encoder;
 use failure::Error;
-use flate2::write::{GzDecoder, GzEncoder};
-use flate2::Compression;
-use std::io::prelude::*;
-use std::path::Path;
+use guard::Guard;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
 use tar::Archive;
 
-pub fn pack_dir<W>(src_path: &AsRef<Path>, w: W) -> Result<W, Error>
+///
+/// Tarballs refused to be unpacked, with the entry refused.
+///
+#[derive(Debug, Fail)]
+pub enum ArchiveError {
+    #[fail(display = "Refused to unpack '{}' ({})", path, reason)]
+    Unsafe { path: String, reason: String },
+    #[fail(
+        display = "Refused to unpack '{}' (files exceed {} bytes in total)",
+        path, limit
+    )]
+    TooLarge { path: String, limit: u64 },
+    #[fail(display = "Refused to unpack '{}' (entries exceed {})", path, limit)]
+    TooManyEntries { path: String, limit: u64 },
+}
+
+///
+/// How tarballs are compressed and which attributes of files they preserve.
+/// `level` is the default of the codec unless it's given, and `threads` is used only by zstd. (All CPUs by default)
+/// Tarballs exceeding the limits are refused on unpacking.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub codec: Codec,
+    pub level: Option<i32>,
+    pub threads: u32,
+    pub attributes: Attributes,
+    pub limits: Limits,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            codec: Codec::default(),
+            level: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get() as u32),
+            attributes: Attributes::default(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn level(&self) -> i32 {
+        self.level.unwrap_or_else(|| self.codec.default_level())
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let (lowest, highest) = self.codec.levels();
+
+        if self.level() < lowest || self.level() > highest {
+            return Err(format_err!(
+                "Level of {} must be from {} to {} (but {} is given)",
+                self.codec,
+                lowest,
+                highest,
+                self.level()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn pack_dir<W>(src_path: &dyn AsRef<Path>, w: W) -> Result<W, Error>
+where
+    W: Write,
+{
+    pack_dir_preserving(src_path, w, &Attributes::default())
+}
+
+///
+/// Pack the directory recording the attributes of files.
+/// Entries are sorted by their names and symlinks are not followed.
+///
+pub fn pack_dir_preserving<W>(
+    src_path: &dyn AsRef<Path>,
+    w: W,
+    attributes: &Attributes,
+) -> Result<W, Error>
 where
     W: Write,
 {
     let mut builder = tar::Builder::new(w);
+    let mut stack = vec![src_path.as_ref().to_path_buf()];
+
+    while let Some(path) = stack.pop() {
+        let meta = fs::symlink_metadata(&path)?;
+
+        if meta.is_dir() {
+            let mut children = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<PathBuf>>>()?;
+            children.sort();
+            stack.extend(children.into_iter().rev());
+        }
+
+        append(&mut builder, &path, &meta, attributes)?;
+    }
 
-    // https://github.com/alexcrichton/tar-rs/issues/174
-    builder.follow_symlinks(false);
-    builder.append_dir_all(src_path, src_path)?;
     builder.finish()?;
     Ok(builder.into_inner()?)
 }
 
-pub fn pack_file<W>(src_path: &AsRef<Path>, w: W) -> Result<W, Error>
+fn append<W>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    meta: &fs::Metadata,
+    attributes: &Attributes,
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    let (mut header, records) = attributes.header(path, meta)?;
+
+    // The PAX header describes the entry right after it.
+    if !records.is_empty() {
+        let mut pax = tar::Header::new_ustar();
+        pax.set_path("././@PaxHeader")?;
+        pax.set_entry_type(tar::EntryType::XHeader);
+        pax.set_mode(0o644);
+        pax.set_size(records.len() as u64);
+        pax.set_cksum();
+        builder.append(&pax, records.as_slice())?;
+    }
+
+    if meta.is_file() {
+        builder.append_data(&mut header, path, fs::File::open(path)?)?;
+    } else if meta.is_dir() {
+        builder.append_data(&mut header, path, io::empty())?;
+    } else if meta.file_type().is_symlink() {
+        header.set_link_name(fs::read_link(path)?)?;
+        builder.append_data(&mut header, path, io::empty())?;
+    } else {
+        return Err(format_err!("{} has unknown file type", path.display()));
+    }
+
+    Ok(())
+}
+
+pub fn pack_file<W>(src_path: &dyn AsRef<Path>, w: W) -> Result<W, Error>
 where
     W: Write,
 {
@@ -34,37 +183,167 @@ where
     Ok(builder.into_inner()?)
 }
 
-pub fn unpack<R>(r: R, dist: &AsRef<Path>) -> Result<(), Error>
+pub fn unpack<R>(r: R, dist: &dyn AsRef<Path>) -> Result<(), Error>
 where
     R: Read,
 {
+    unpack_with(r, dist, &Options::default())
+}
+
+///
+/// Unpack the tarball restoring the attributes of files recorded in it.
+/// Every entry is validated before it's unpacked, and the tarball is refused
+/// by `ArchiveError` at the first entry escaping `dist` or exceeding the limits.
+///
+/// Entries are unpacked into a staging directory in `dist` and moved into `dist` only when all of them are accepted,
+/// so that refused tarballs leave nothing behind and links are never resolved through files which were there before.
+///
+pub fn unpack_with<R>(r: R, dist: &dyn AsRef<Path>, options: &Options) -> Result<(), Error>
+where
+    R: Read,
+{
+    let attributes = &options.attributes;
+    fs::create_dir_all(dist)?;
+    let staging = tempfile::Builder::new()
+        .prefix(".famo-unpacking")
+        .tempdir_in(dist)?;
+    let mut guard = Guard::new(staging.path(), &options.limits)?;
     let mut archive = Archive::new(r);
-    archive.unpack(dist)?;
+    let mut directories = vec![];
+
+    // Mtimes are restored in nanoseconds below instead of seconds by tar.
+    archive.set_preserve_mtime(false);
+    archive.set_preserve_permissions(attributes.permissions);
+    archive.set_unpack_xattrs(attributes.xattrs);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        guard.check(&entry)?;
+
+        if !entry.unpack_in(staging.path())? {
+            continue;
+        }
+
+        let path = destination(&entry.path()?);
+
+        if let Some(mtime) = attributes.restore(&mut entry, &staging.path().join(&path))? {
+            directories.push((path, mtime));
+        }
+    }
+
+    guard.verify()?;
+    merge(staging.path(), dist.as_ref())?;
+
+    // Children are restored before their parents, after moving files into them.
+    for (path, mtime) in directories.iter().rev() {
+        filetime::set_file_times(dist.as_ref().join(path), *mtime, *mtime)?;
+    }
 
     Ok(())
 }
 
+// Move files into the destination replacing the ones there, and merge directories into the existing ones.
+fn merge(src: &Path, dst: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        let existing = fs::symlink_metadata(&target).ok();
+
+        match existing {
+            Some(ref meta) if meta.is_dir() && entry.file_type()?.is_dir() => {
+                merge(&entry.path(), &target)?;
+                continue;
+            }
+            Some(ref meta) if meta.is_dir() => fs::remove_dir_all(&target)?,
+            Some(_) => fs::remove_file(&target)?,
+            None => {}
+        }
+
+        fs::rename(entry.path(), &target)?;
+    }
+
+    Ok(())
+}
+
+// Where tar unpacks the entry under the destination, ignoring roots and `.` of the path.
+fn destination(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+///
+/// Stream a gzipped tarball of the directory into the writer.
+/// Nothing is buffered beyond the internal buffers of tar and gzip.
+///
+pub fn pack_dir_encoded<W>(src_path: &dyn AsRef<Path>, w: W) -> Result<W, Error>
+where
+    W: Write,
+{
+    pack_dir_with(src_path, w, &Options::default())
+}
+
+///
+/// Stream a tarball of the directory compressed by the codec of the options into the writer.
+///
+pub fn pack_dir_with<W>(src_path: &dyn AsRef<Path>, w: W, options: &Options) -> Result<W, Error>
+where
+    W: Write,
+{
+    let encoder = pack_dir_preserving(src_path, Encoder::new(w, options)?, &options.attributes)?;
+    encoder.finish()
+}
+
+///
+/// Stream a tarball from the reader and unpack it into `dist`.
+/// The codec is detected from the head of the stream.
+///
+pub fn unpack_encoded<R>(r: R, dist: &dyn AsRef<Path>) -> Result<(), Error>
+where
+    R: Read,
+{
+    unpack_encoded_with(r, dist, &Options::default())
+}
+
+pub fn unpack_encoded_with<R>(r: R, dist: &dyn AsRef<Path>, options: &Options) -> Result<(), Error>
+where
+    R: Read,
+{
+    unpack_with(codec::decoder(r)?, dist, options)
+}
+
 pub fn encode<W>(data: &[u8], w: W) -> Result<W, Error>
 where
     W: Write,
 {
-    let mut encoder = GzEncoder::new(w, Compression::default());
+    encode_with(data, w, &Options::default())
+}
+
+pub fn encode_with<W>(data: &[u8], w: W, options: &Options) -> Result<W, Error>
+where
+    W: Write,
+{
+    let mut encoder = Encoder::new(w, options)?;
     encoder.write_all(data)?;
-    Ok(encoder.finish()?)
+    encoder.finish()
 }
 
-pub fn decode<W>(data: &[u8], w: W) -> Result<W, Error>
+pub fn decode<W>(data: &[u8], mut w: W) -> Result<W, Error>
 where
     W: Write,
 {
-    let mut decoder = GzDecoder::new(w);
-    decoder.write_all(data)?;
-    Ok(decoder.finish()?)
+    io::copy(&mut codec::decoder(data)?, &mut w)?;
+    Ok(w)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use filetime::FileTime;
+    use std::{env, process};
 
     #[test]
     fn encode_and_decode() {
@@ -74,4 +353,243 @@ mod test {
 
         assert_eq!(data, decoded.as_slice());
     }
+
+    #[test]
+    fn pack_and_unpack_encoded() {
+        let tarball = pack_dir_encoded(&"src", Vec::new()).unwrap();
+
+        let dist = env::temp_dir().join(format!("famo-archive-{}", process::id()));
+        unpack_encoded(tarball.as_slice(), &dist).unwrap();
+
+        assert_eq!(
+            fs::read(dist.join("src/lib.rs")).unwrap(),
+            fs::read("src/lib.rs").unwrap()
+        );
+
+        fs::remove_dir_all(&dist).unwrap();
+    }
+
+    #[test]
+    fn unpack_whichever_codec_packed() {
+        for codec in &["zstd", "xz", "none"] {
+            let options = Options {
+                codec: codec.parse().unwrap(),
+                threads: 2,
+                ..Options::default()
+            };
+            let tarball = pack_dir_with(&"src", Vec::new(), &options).unwrap();
+
+            let dist = env::temp_dir().join(format!("famo-archive-{}-{}", codec, process::id()));
+            unpack_encoded(tarball.as_slice(), &dist).unwrap();
+
+            assert_eq!(
+                fs::read(dist.join("src/codec.rs")).unwrap(),
+                fs::read("src/codec.rs").unwrap()
+            );
+
+            fs::remove_dir_all(&dist).unwrap();
+        }
+    }
+
+    #[test]
+    fn mtimes_are_restored_in_nanoseconds() {
+        // Tarballs record paths relative to the working directory as famo packs them.
+        let tmp = tempfile::Builder::new()
+            .prefix("attributes")
+            .tempdir_in(".")
+            .unwrap();
+        let src = tmp.path().file_name().unwrap();
+        let file = Path::new(src).join("dir").join("file");
+        let mtime = FileTime::from_unix_time(1_546_300_800, 123_456_789);
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "famo").unwrap();
+        filetime::set_file_times(&file, mtime, mtime).unwrap();
+        filetime::set_file_times(file.parent().unwrap(), mtime, mtime).unwrap();
+
+        let mtime_of =
+            |path: &Path| FileTime::from_last_modification_time(&fs::metadata(path).unwrap());
+        let restore = |attributes: &Attributes| {
+            let tarball = pack_dir_preserving(&src, Vec::new(), attributes).unwrap();
+            let dist = tempfile::tempdir().unwrap();
+            let options = Options {
+                attributes: attributes.clone(),
+                ..Options::default()
+            };
+            unpack_with(tarball.as_slice(), &dist.path(), &options).unwrap();
+
+            let mtimes = (
+                mtime_of(&dist.path().join(&file)),
+                mtime_of(&dist.path().join(file.parent().unwrap())),
+            );
+            (tarball, mtimes)
+        };
+
+        let (_, mtimes) = restore(&Attributes::default());
+        assert_eq!(mtimes, (mtime, mtime));
+
+        let normalized = Attributes {
+            normalized_mtime: Some(0),
+            ..Attributes::default()
+        };
+        let (tarball, mtimes) = restore(&normalized);
+        assert_eq!(mtimes, (FileTime::zero(), FileTime::zero()));
+
+        // Tarballs are identical however the files are touched.
+        filetime::set_file_times(&file, FileTime::zero(), FileTime::zero()).unwrap();
+        assert_eq!(restore(&normalized).0, tarball);
+    }
+
+    // Tarballs planted by others, whose paths can't be written by `Header::set_path`.
+    fn planted(entries: &[(&str, tar::EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, kind, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o755);
+
+            if kind.is_symlink() || kind.is_hard_link() {
+                header.set_link_name(data).unwrap();
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            } else {
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, data.as_bytes()).unwrap();
+            }
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn refused(entries: &[(&str, tar::EntryType, &str)], options: &Options) -> Option<String> {
+        let dist = tempfile::tempdir().unwrap();
+        let result = unpack_with(planted(entries).as_slice(), &dist.path(), options);
+
+        // Refused tarballs leave nothing behind.
+        if result.is_err() {
+            assert_eq!(fs::read_dir(dist.path()).unwrap().count(), 0);
+        }
+
+        match result {
+            Ok(()) => None,
+            Err(e) => match e.downcast::<ArchiveError>().unwrap() {
+                ArchiveError::Unsafe { path, .. }
+                | ArchiveError::TooLarge { path, .. }
+                | ArchiveError::TooManyEntries { path, .. } => Some(path),
+            },
+        }
+    }
+
+    #[test]
+    fn entries_escaping_the_destination_are_refused() {
+        use tar::EntryType::{Directory, Link, Regular, Symlink};
+
+        let options = Options::default();
+        let refused = |entries: &[(&str, tar::EntryType, &str)]| refused(entries, &options);
+
+        assert_eq!(
+            refused(&[("../evil", Regular, "evil")]),
+            Some("../evil".to_owned())
+        );
+        assert_eq!(
+            refused(&[("/tmp/evil", Regular, "evil")]),
+            Some("/tmp/evil".to_owned())
+        );
+        assert_eq!(
+            refused(&[("target/link", Symlink, "/etc")]),
+            Some("target/link".to_owned())
+        );
+        assert_eq!(
+            refused(&[("target/link", Symlink, "../..")]),
+            Some("target/link".to_owned())
+        );
+        assert_eq!(
+            refused(&[("target/link", Link, "../etc/passwd")]),
+            Some("target/link".to_owned())
+        );
+        assert_eq!(
+            refused(&[("target/fifo", tar::EntryType::Fifo, "")]),
+            Some("target/fifo".to_owned())
+        );
+
+        // Symlinks unpacked before are resolved, which lexical checks miss.
+        assert_eq!(
+            refused(&[
+                ("a/b", Directory, ""),
+                ("a/b/up", Symlink, ".."),
+                ("a/b/up/escape", Symlink, "../.."),
+            ]),
+            Some("a/b/up/escape".to_owned())
+        );
+        assert_eq!(
+            refused(&[
+                ("b", Directory, ""),
+                ("b/c", Symlink, ".."),
+                ("a", Symlink, "b/c/.."),
+            ]),
+            Some("a".to_owned())
+        );
+
+        // Symlinks are verified again after symlinks unpacked later change where they point to.
+        assert_eq!(
+            refused(&[
+                ("a", Symlink, "b/c/.."),
+                ("b", Directory, ""),
+                ("b/c", Symlink, ".."),
+            ]),
+            Some("a".to_owned())
+        );
+
+        assert_eq!(
+            refused(&[
+                ("target/debug", Directory, ""),
+                ("target/debug/famo", Regular, "famo"),
+                ("target/latest", Symlink, "debug/../debug/famo"),
+                ("target/hard", Link, "target/debug/famo"),
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    fn unpacked_files_are_merged_into_the_destination() {
+        use tar::EntryType::{Directory, Regular};
+
+        let dist = tempfile::tempdir().unwrap();
+        fs::create_dir(dist.path().join("target")).unwrap();
+        fs::write(dist.path().join("target").join("kept"), "kept").unwrap();
+        fs::write(dist.path().join("target").join("famo"), "old").unwrap();
+
+        let tarball = planted(&[("target", Directory, ""), ("target/famo", Regular, "new")]);
+        unpack_with(tarball.as_slice(), &dist.path(), &Options::default()).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dist.path().join("target").join(name)).unwrap();
+        assert_eq!(
+            (read("kept"), read("famo")),
+            ("kept".to_owned(), "new".to_owned())
+        );
+        assert_eq!(fs::read_dir(dist.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn tarballs_exceeding_limits_are_refused() {
+        use tar::EntryType::Regular;
+
+        let entries = [("a", Regular, "famo"), ("b", Regular, "famo")];
+        let limited = |max_size, max_entries| Options {
+            limits: Limits {
+                max_size,
+                max_entries,
+            },
+            ..Options::default()
+        };
+
+        assert_eq!(refused(&entries, &limited(8, 2)), None);
+        assert_eq!(refused(&entries, &limited(7, 2)), Some("b".to_owned()));
+        assert_eq!(refused(&entries, &limited(8, 1)), Some("b".to_owned()));
+    }
 }
@@ -0,0 +1,449 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate failure;
+extern crate aes_gcm;
+extern crate base64;
+extern crate rand;
+extern crate sha2;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use failure::Error;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+/// Head of encrypted caches.
+pub const MAGIC: &[u8] = b"FAMOENC1";
+
+/// Bytes of plaintext sealed by each chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Debug, Fail)]
+pub enum CryptError {
+    #[fail(display = "Invalid encryption key ({})", reason)]
+    InvalidKey { reason: String },
+    #[fail(
+        display = "The cache is encrypted by the key '{}' which isn't given (--encryption_key)",
+        key_id
+    )]
+    UnknownKey { key_id: String },
+    #[fail(display = "The cache is not encrypted while encryption keys are given")]
+    NotEncrypted,
+    #[fail(display = "The encrypted cache is corrupted or tampered")]
+    Corrupted,
+}
+
+///
+/// A key of AES-256-GCM. Its ID is the fingerprint of the key, stored with caches encrypted by it.
+///
+#[derive(Clone)]
+pub struct Key {
+    id: String,
+    secret: [u8; 32],
+}
+
+impl Key {
+    pub fn new(secret: [u8; 32]) -> Self {
+        let id = Sha256::digest(secret)[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Self { id, secret }
+    }
+
+    ///
+    /// 32 bytes encoded by base64, such as the output of `openssl rand -base64 32`.
+    ///
+    pub fn parse(key: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(key.trim()).map_err(|e| CryptError::InvalidKey {
+            reason: e.to_string(),
+        })?;
+
+        if bytes.len() != 32 {
+            return Err(CryptError::InvalidKey {
+                reason: format!(
+                    "{} bytes are given while AES-256 requires 32 bytes",
+                    bytes.len()
+                ),
+            }
+            .into());
+        }
+
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&bytes);
+
+        Ok(Self::new(secret))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.secret.into())
+    }
+}
+
+// Secrets never appear in logs.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key({})", self.id)
+    }
+}
+
+///
+/// Keys in the file, one key per line. Empty lines and lines starting with `#` are skipped.
+///
+pub fn keys_of_file(path: &dyn AsRef<Path>) -> Result<Vec<Key>, Error> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Key::parse)
+        .collect()
+}
+
+///
+/// A writer which encrypts everything written into the inner writer.
+///
+/// The header (`MAGIC`, the key ID and a random base of nonces) is followed by chunks of `CHUNK_SIZE` bytes,
+/// each of which is sealed with the header as associated data and a nonce of the base mixed with its index and whether it's the last one.
+/// So chunks can't be altered, reordered or truncated without failing decryption. `finish` must be called to seal the last chunk.
+///
+/// The base is as long as the nonce, 96 bits, so that nonces of different caches sealed by the same key hardly collide.
+///
+pub struct Encryptor<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    base: [u8; NONCE_SIZE],
+    counter: u32,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encryptor<W> {
+    pub fn new(mut inner: W, key: &Key) -> Result<Self, Error> {
+        let mut base = [0; NONCE_SIZE];
+        OsRng::new()?.fill_bytes(&mut base);
+
+        let mut header = MAGIC.to_vec();
+        header.push(key.id.len() as u8);
+        header.extend(key.id.as_bytes());
+        header.extend(&base);
+
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher: key.cipher(),
+            header,
+            base,
+            counter: 0,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    ///
+    /// Seal the last chunk and return the inner writer.
+    /// The last chunk is always shorter than `CHUNK_SIZE`, so it may be empty.
+    ///
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.buf.len() == CHUNK_SIZE {
+            self.seal(false)?;
+        }
+
+        self.seal(true)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let nonce = nonce(&self.base, self.counter, last);
+        let payload = Payload {
+            msg: &self.buf,
+            aad: &self.header,
+        };
+
+        // The tag follows the sealed chunk.
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Failed to encrypt"))?;
+
+        self.inner.write_all(&sealed)?;
+        self.buf.clear();
+
+        self.counter = self.counter.checked_add(1).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Too large to be encrypted")
+        })?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is kept until more bytes come, since the last chunk must be shorter than it.
+        if self.buf.len() == CHUNK_SIZE {
+            self.seal(false)?;
+        }
+
+        let n = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend(&buf[..n]);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+///
+/// A reader which decrypts caches written by `Encryptor`.
+/// Each chunk is authenticated before any byte of it is read.
+///
+pub struct Decryptor<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    base: [u8; NONCE_SIZE],
+    counter: u32,
+    plain: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Decryptor<R> {
+    fn open(&mut self) -> Result<(), Error> {
+        let mut sealed = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE);
+        (&mut self.inner)
+            .take((CHUNK_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut sealed)?;
+
+        if sealed.len() < TAG_SIZE {
+            return Err(CryptError::Corrupted.into());
+        }
+
+        let last = sealed.len() < CHUNK_SIZE + TAG_SIZE;
+        let nonce = nonce(&self.base, self.counter, last);
+        let payload = Payload {
+            msg: &sealed,
+            aad: &self.header,
+        };
+
+        self.plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| CryptError::Corrupted)?;
+        self.pos = 0;
+        self.counter = self.counter.checked_add(1).ok_or(CryptError::Corrupted)?;
+
+        if last {
+            self.done = true;
+
+            // Nothing follows the last chunk.
+            if self.inner.read(&mut [0])? != 0 {
+                return Err(CryptError::Corrupted.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() && !self.done {
+            self.open().map_err(|e| match e.downcast::<io::Error>() {
+                Ok(e) => e,
+                Err(e) => io::Error::new(io::ErrorKind::InvalidData, e.compat()),
+            })?;
+        }
+
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+///
+/// Decrypt the cache by the key of its ID.
+/// Caches which aren't encrypted are read as they are only when no keys are given,
+/// so that nobody can replace encrypted caches with plaintext ones.
+///
+pub fn decrypt<R>(mut reader: R, keys: &[Key]) -> Result<Box<dyn Read + Send>, Error>
+where
+    R: Read + Send + 'static,
+{
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    (&mut reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+
+    if magic != MAGIC {
+        if !keys.is_empty() {
+            return Err(CryptError::NotEncrypted.into());
+        }
+
+        return Ok(Box::new(Cursor::new(magic).chain(reader)));
+    }
+
+    let mut len = [0];
+    reader.read_exact(&mut len)?;
+
+    let mut key_id = vec![0; len[0] as usize];
+    reader.read_exact(&mut key_id)?;
+    let key_id = String::from_utf8_lossy(&key_id).into_owned();
+
+    let mut base = [0; NONCE_SIZE];
+    reader.read_exact(&mut base)?;
+
+    let key = match keys.iter().find(|key| key.id == key_id) {
+        Some(key) => key,
+        None => return Err(CryptError::UnknownKey { key_id }.into()),
+    };
+
+    let mut header = magic;
+    header.extend(&len);
+    header.extend(key_id.as_bytes());
+    header.extend(&base);
+
+    Ok(Box::new(Decryptor {
+        inner: reader,
+        cipher: key.cipher(),
+        header,
+        base,
+        counter: 0,
+        plain: vec![],
+        pos: 0,
+        done: false,
+    }))
+}
+
+// The base XORed with {0 (7 bytes)}{index of the chunk (4 bytes)}{1 for the last chunk}
+fn nonce(base: &[u8; NONCE_SIZE], counter: u32, last: bool) -> [u8; NONCE_SIZE] {
+    let mut nonce = *base;
+
+    for (byte, index) in nonce[7..11].iter_mut().zip(&counter.to_be_bytes()) {
+        *byte ^= index;
+    }
+    nonce[11] ^= last as u8;
+
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(byte: u8) -> Key {
+        Key::new([byte; 32])
+    }
+
+    fn encrypt(data: &[u8], key: &Key) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(vec![], key).unwrap();
+        encryptor.write_all(data).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    fn decrypt_all(sealed: Vec<u8>, keys: &[Key]) -> Result<Vec<u8>, Error> {
+        let mut data = vec![];
+        decrypt(Cursor::new(sealed), keys)?.read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    #[test]
+    fn encrypted_caches_are_decrypted_by_key_of_their_id() {
+        let keys = [key(1), key(2)];
+
+        for len in &[0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE * 2 + 3] {
+            let data = (0..*len).map(|i| i as u8).collect::<Vec<u8>>();
+            let sealed = encrypt(&data, &keys[1]);
+
+            assert_ne!(&sealed[MAGIC.len()..], &data[..]);
+            assert_eq!(decrypt_all(sealed, &keys).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn tampered_or_truncated_caches_are_rejected() {
+        let keys = [key(1)];
+        let sealed = encrypt(&[7; CHUNK_SIZE * 2], &keys[0]);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_all(tampered, &keys).is_err());
+
+        // Truncated at the boundary of chunks.
+        let header = MAGIC.len() + 1 + keys[0].id().len() + NONCE_SIZE;
+        let truncated = sealed[..header + CHUNK_SIZE + TAG_SIZE].to_vec();
+        assert!(decrypt_all(truncated, &keys).is_err());
+
+        let mut extended = sealed.clone();
+        extended.push(0);
+        assert!(decrypt_all(extended, &keys).is_err());
+    }
+
+    #[test]
+    fn nonces_differ_by_chunks_and_caches() {
+        let base = [0xff; NONCE_SIZE];
+
+        assert_eq!(nonce(&base, 0, false), base);
+        assert_ne!(nonce(&base, 0, true), nonce(&base, 0, false));
+        assert_ne!(nonce(&base, 1, false), nonce(&base, 0, false));
+        assert_ne!(nonce(&base, 1 << 8, true), nonce(&base, 1, true));
+
+        // Each cache has its own base of nonces.
+        let header = MAGIC.len() + 1 + key(1).id().len();
+        let (first, second) = (encrypt(b"secret", &key(1)), encrypt(b"secret", &key(1)));
+        assert_ne!(
+            &first[header..header + NONCE_SIZE],
+            &second[header..header + NONCE_SIZE]
+        );
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn keys_must_match_caches() {
+        let sealed = encrypt(b"secret", &key(1));
+
+        match decrypt_all(sealed.clone(), &[key(2)])
+            .unwrap_err()
+            .downcast()
+        {
+            Ok(CryptError::UnknownKey { key_id }) => assert_eq!(key_id, key(1).id()),
+            e => panic!("Unexpected result {:?}", e),
+        }
+        assert!(decrypt_all(sealed, &[]).is_err());
+
+        assert!(decrypt_all(b"plain".to_vec(), &[key(1)]).is_err());
+        assert_eq!(decrypt_all(b"plain".to_vec(), &[]).unwrap(), b"plain");
+    }
+
+    #[test]
+    fn keys_are_parsed_from_base64() {
+        let key = Key::parse("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=\n").unwrap();
+
+        assert_eq!(key.id(), self::key(1).id());
+        assert_eq!(key.id().len(), 16);
+        assert_eq!(format!("{:?}", key), format!("Key({})", key.id()));
+        assert!(Key::parse("AQEB").is_err());
+        assert!(Key::parse("not base64!").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use failure::Error;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use {Leaf, COMPONENT};
+
+///
+/// The platform famo is running on, such as `x86_64-linux-gnu`.
+///
+/// ```rust
+/// use famo_hash::components::triple;
+///
+/// println!("famo is running on {}", triple());
+/// ```
+///
+pub fn triple() -> String {
+    format!("{}-{}-{}", env::consts::ARCH, env::consts::OS, abi())
+}
+
+// Releases for Linux are linked statically with musl and run on glibc systems as well,
+// so the libc is found by the dynamic loader of the system instead of the target famo is built for.
+#[cfg(target_os = "linux")]
+fn abi() -> &'static str {
+    libc_in(Path::new("/"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abi() -> &'static str {
+    if cfg!(target_env = "gnu") {
+        "gnu"
+    } else if cfg!(target_env = "msvc") {
+        "msvc"
+    } else {
+        "none"
+    }
+}
+
+// Loaders are `lib/ld-musl-{arch}.so.1` of musl and `lib*/ld-linux-{arch}.so.*` of glibc.
+// Systems with both of them (e.g. Alpine with gcompat) are musl ones.
+#[cfg(any(target_os = "linux", test))]
+fn libc_in(root: &Path) -> &'static str {
+    let has_loader = |prefix: &str| {
+        ["lib", "lib64"].iter().any(|dir| {
+            fs::read_dir(root.join(dir))
+                .map(|entries| {
+                    entries
+                        .filter_map(Result::ok)
+                        .any(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+                })
+                .unwrap_or(false)
+        })
+    };
+
+    if has_loader("ld-musl-") {
+        "musl"
+    } else if has_loader("ld-linux") {
+        "gnu"
+    } else {
+        "none"
+    }
+}
+
+///
+/// A Leaf of the platform, so that caches are never restored onto other platforms.
+///
+pub fn platform() -> Leaf {
+    component("platform", triple().as_bytes())
+}
+
+///
+/// A Leaf of the environment variable. Variables which aren't set differ from empty ones.
+///
+pub fn env(name: &str) -> Leaf {
+    env_with(name, &|name| {
+        env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+    })
+}
+
+///
+/// `env` reading the variable by `var` instead of the environment.
+///
+pub fn env_with(name: &str, var: &dyn Fn(&str) -> Option<String>) -> Leaf {
+    let value = var(name).map(|value| format!("={}", value));
+
+    component(
+        &format!("env:{}", name),
+        value.unwrap_or_default().as_bytes(),
+    )
+}
+
+///
+/// A Leaf of the output of the command executed by `sh -c`, such as `rustc -vV`.
+/// Commands which fail are also hashed with their exit status, since a missing toolchain is also a kind of toolchain.
+///
+pub fn probe(command: &str) -> Result<Leaf, Error> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        warn!("`{}` for the key failed ({})", command, output.status);
+    }
+
+    let mut value = output.stdout;
+    value.extend(format!("\n{:?}", output.status.code()).as_bytes());
+
+    Ok(component(&format!("probe:{}", command), &value))
+}
+
+fn component(name: &str, value: &[u8]) -> Leaf {
+    Leaf {
+        path: name.to_owned(),
+        kind: COMPONENT,
+        digest: Sha256::digest(value).to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unset_env_differs_from_empty_one() {
+        let unset = env_with("RUSTFLAGS", &|_| None);
+        let empty = env_with("RUSTFLAGS", &|_| Some(String::new()));
+
+        assert_ne!(unset, empty);
+        assert_eq!(empty.path(), "env:RUSTFLAGS");
+        assert_ne!(empty, env_with("RUSTFLAGS", &|_| Some("-C".to_owned())));
+    }
+
+    #[test]
+    fn libc_is_found_by_loaders() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(libc_in(root.path()), "none");
+
+        fs::create_dir_all(root.path().join("lib64")).unwrap();
+        fs::write(root.path().join("lib64/ld-linux-x86-64.so.2"), "").unwrap();
+        assert_eq!(libc_in(root.path()), "gnu");
+
+        fs::create_dir_all(root.path().join("lib")).unwrap();
+        fs::write(root.path().join("lib/ld-musl-x86_64.so.1"), "").unwrap();
+        assert_eq!(libc_in(root.path()), "musl");
+    }
+
+    #[test]
+    fn probes_hash_outputs_and_status() {
+        let probe0 = probe("echo 1.30").unwrap();
+
+        assert_eq!(probe0, probe("echo 1.30").unwrap());
+        assert_ne!(probe0.digest(), probe("echo 1.31").unwrap().digest());
+        assert_ne!(
+            probe("echo 1.30; exit 1").unwrap().digest(),
+            probe0.digest()
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use failure::Error;
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::Path;
+use Options;
+
+///
+/// Decide which entries found by traversing watched directories are skipped.
+/// Paths are relative to the working directory as Leaf.
+///
+pub struct Filter {
+    excludes: GlobSet,
+    gitignore: bool,
+    // `.gitignore` of the working directory and directories being read, from the outermost.
+    gitignores: Vec<Gitignore>,
+}
+
+impl Filter {
+    pub fn new(options: &Options) -> Result<Filter, Error> {
+        let mut filter = Filter {
+            excludes: glob_set(&options.excludes)?,
+            gitignore: options.gitignore,
+            gitignores: vec![],
+        };
+
+        filter.enter(Path::new("."))?;
+
+        Ok(filter)
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.excludes.is_match(path)
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.gitignore {
+            return false;
+        }
+
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+
+        // Rules of inner `.gitignore` override outer ones.
+        for gitignore in self.gitignores.iter().rev() {
+            match gitignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        false
+    }
+
+    ///
+    /// Load `.gitignore` of the directory before reading it.
+    /// Returns true if it's loaded, which has to be passed to `leave` after reading the directory.
+    ///
+    pub fn enter(&mut self, dir: &Path) -> Result<bool, Error> {
+        let path = dir.join(".gitignore");
+
+        if !self.gitignore || !path.is_file() || self.is_loaded(dir) {
+            return Ok(false);
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+
+        if let Some(e) = builder.add(&path) {
+            return Err(e.into());
+        }
+
+        self.gitignores.push(builder.build()?);
+
+        Ok(true)
+    }
+
+    pub fn leave(&mut self, entered: bool) {
+        if entered {
+            self.gitignores.pop();
+        }
+    }
+
+    // `.` and `./` are the working directory loaded first.
+    fn is_loaded(&self, dir: &Path) -> bool {
+        let root = |path: &Path| path.components().all(|c| c.as_os_str() == ".");
+
+        root(dir)
+            && self
+                .gitignores
+                .iter()
+                .any(|gitignore| root(gitignore.path()))
+    }
+}
+
+///
+/// Glob patterns matching paths relative to the working directory.
+/// `*` doesn't match `/` while `**` matches any directories.
+///
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(
+            GlobBuilder::new(pattern.trim_start_matches("./"))
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+
+    Ok(builder.build()?)
+}
+
+///
+/// A glob pattern matched while traversing directories,
+/// which tells directories that can't contain matches so that they are never read.
+///
+pub struct Pattern {
+    matcher: GlobSet,
+    // Each component of the pattern, where `None` is `**` matching any directories.
+    // Patterns whose braces may contain `/` can't be split, and every directory may contain matches of them.
+    components: Option<Vec<Option<GlobMatcher>>>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, Error> {
+        let pattern = pattern.trim_start_matches("./");
+        let components = if pattern.contains('{') {
+            None
+        } else {
+            let components = pattern
+                .split('/')
+                .map(|component| {
+                    if component.contains("**") {
+                        return Ok(None);
+                    }
+
+                    let matcher = GlobBuilder::new(component)
+                        .literal_separator(true)
+                        .build()?
+                        .compile_matcher();
+
+                    Ok(Some(matcher))
+                })
+                .collect::<Result<Vec<Option<GlobMatcher>>, Error>>()?;
+
+            Some(components)
+        };
+
+        Ok(Pattern {
+            matcher: glob_set(&[pattern.to_owned()])?,
+            components,
+        })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.matcher.is_match(path)
+    }
+
+    ///
+    /// Whether entries under the directory may match the pattern.
+    ///
+    pub fn may_contain(&self, dir: &str) -> bool {
+        let components = match self.components {
+            Some(ref components) => components,
+            None => return true,
+        };
+
+        let mut depth = 0;
+
+        for name in dir.split('/') {
+            match components.get(depth) {
+                Some(None) => return true,
+                Some(Some(matcher)) if matcher.is_match(name) => depth += 1,
+                _ => return false,
+            }
+        }
+
+        depth < components.len()
+    }
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(|c| "*?[{".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn directories_which_may_contain_matches() {
+        let pattern = Pattern::new("./b/*/*.rs").unwrap();
+
+        assert!(pattern.is_match("b/c/main.rs"));
+        assert!(pattern.may_contain("b"));
+        assert!(pattern.may_contain("b/c"));
+        assert!(!pattern.may_contain("a"));
+        assert!(!pattern.may_contain("b/c/d"));
+
+        let pattern = Pattern::new("/tmp/**/Cargo.toml").unwrap();
+
+        assert!(pattern.may_contain("/tmp/a/b"));
+        assert!(!pattern.may_contain("/var"));
+        assert!(Pattern::new("{a,b/c}/*.rs").unwrap().may_contain("d"));
+    }
+}
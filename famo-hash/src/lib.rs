@@ -1,195 +1,803 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
 extern crate failure;
+extern crate globset;
+extern crate ignore;
+#[macro_use]
+extern crate log;
 extern crate num;
 extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
 
-use failure::Error;
-use num::bigint::BigUint;
+pub mod components;
+mod filter;
+
+///
+/// The scheme of keys before versioning, which sums SHA-256 of each file as BigUint.
+/// It's kept to find caches stored under these keys.
+///
+pub mod legacy;
+
+use failure::{err_msg, Error};
+use filter::{Filter, Pattern};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::Path;
-use sha2::{Sha256, Digest};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+///
+/// Version of the key derivation. Keys start with `v{VERSION}-`,
+/// so that a new scheme never finds caches stored by another one.
+///
+pub const VERSION: u32 = 1;
+
+// Prefixes of hashed values, which distinguish files, links and other components of keys from nodes of the tree.
+const FILE: u8 = 0;
+const NODE: u8 = 1;
+const LINK: u8 = 2;
+const COMPONENT: u8 = 3;
+
+#[derive(Debug, Fail)]
+pub enum HashError {
+    #[fail(display = "Failed to read '{}': {}", path, description)]
+    Unreadable { path: String, description: String },
+    #[fail(display = "Symbolic link '{}' makes a cycle to '{}'", path, target)]
+    SymlinkCycle { path: String, target: String },
+    #[fail(display = "'{}' matches nothing to be watched", pattern)]
+    NoMatch { pattern: String },
+}
+
+///
+/// How symbolic links in watched directories are hashed.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Symlinks {
+    /// Hash files and directories which links point to as if they were at the links.
+    #[default]
+    Follow,
+    /// Hash paths which links point to without reading them.
+    Link,
+}
+
+impl FromStr for Symlinks {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "follow" => Ok(Symlinks::Follow),
+            "link" => Ok(Symlinks::Link),
+            _ => Err(format_err!("Unknown policy of symbolic links '{}'", s)),
+        }
+    }
+}
+
+///
+/// What to do when a watched path or pattern matches nothing.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Missing {
+    #[default]
+    Error,
+    Warn,
+}
+
+impl FromStr for Missing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "error" => Ok(Missing::Error),
+            "warn" => Ok(Missing::Warn),
+            _ => Err(format_err!("Unknown policy of missing paths '{}'", s)),
+        }
+    }
+}
+
+///
+/// Options of traversing watched paths.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub symlinks: Symlinks,
+    /// Glob patterns of paths skipped in watched directories and patterns.
+    pub excludes: Vec<String>,
+    /// Skip entries ignored by `.gitignore` of the working directory and watched directories.
+    pub gitignore: bool,
+    pub missing: Missing,
+}
+
+///
+/// A file identified by its path relative to the working directory and SHA-256 of its contents.
+/// A symbolic link which isn't followed is identified by SHA-256 of the path it points to.
+/// Other components of the key (see `components`) are identified by their names such as `env:RUSTFLAGS`.
+///
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Leaf {
+    path: String,
+    kind: u8,
+    digest: Vec<u8>,
+}
+
+impl Leaf {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    pub fn is_link(&self) -> bool {
+        self.kind == LINK
+    }
+
+    // SHA256(kind || length of the path || path || digest of the contents, the link or the component)
+    fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.input([self.kind]);
+        hasher.input((self.path.len() as u64).to_be_bytes());
+        hasher.input(self.path.as_bytes());
+        hasher.input(&self.digest);
+        hasher.result().to_vec()
+    }
+}
 
 ///
 /// Calculate unique hex from paths of files and directories.
+/// The hex always has the same length and starts with the version.
 ///
 /// ```rust
 /// use famo_hash::hex;
 ///
-/// let paths = vec!["Cargo.toml", "Cargo.lock"];
-/// let hex = hex(&paths).unwrap();
+/// let paths = vec!["Cargo.toml", "src"];
+/// let hex = hex(&paths, &Default::default()).unwrap();
 /// println!("The unique hex is {}", hex);
 /// ```
-pub fn hex(paths: &Vec<&str>) -> Result<String, Error> {
-    let s = sum(paths)?;
-    let h = format!("{:x}", s);
+pub fn hex(paths: &Vec<&str>, options: &Options) -> Result<String, Error> {
+    let h = hex_of(read(paths, options)?);
 
     Ok(h)
 }
 
 ///
-/// Calculate sum of each BigUint of files.
+/// Calculate unique hex from leaves, which are files read by `read` and other components of the key.
+///
+/// ```rust
+/// use famo_hash::{components, hex_of, read};
+///
+/// let mut leaves = read(&vec!["Cargo.toml"], &Default::default()).unwrap();
+/// leaves.push(components::platform());
+/// leaves.push(components::env("RUSTFLAGS"));
+///
+/// println!("The unique hex is {}", hex_of(leaves));
+/// ```
+pub fn hex_of(leaves: Vec<Leaf>) -> String {
+    format!("v{}-{}", VERSION, to_hex(&digest_of(leaves)))
+}
+
+///
+/// Calculate the root of the Merkle tree whose leaves are files sorted by their paths.
+/// The order and way to specify paths don't affect the root.
 ///
 /// ```rust
-/// use famo_hash::sum;
+/// use famo_hash::digest;
 ///
-/// let paths = vec!["Cargo.toml", "Cargo.lock", "target"];
-/// let sum = sum(&paths).unwrap();
-/// println!("The sum of these files is {}", sum);
+/// let paths = vec!["Cargo.toml", "src"];
+/// let digest = digest(&paths, &Default::default()).unwrap();
+/// assert_eq!(digest.len(), 32);
 /// ```
-pub fn sum(paths: &Vec<&str>) -> Result<BigUint, Error> {
-    let s = read(paths)?.iter().sum::<BigUint>();
+pub fn digest(paths: &Vec<&str>, options: &Options) -> Result<Vec<u8>, Error> {
+    Ok(digest_of(read(paths, options)?))
+}
 
-    Ok(s)
+///
+/// Calculate the root of the Merkle tree whose leaves are sorted by their paths.
+///
+pub fn digest_of(mut leaves: Vec<Leaf>) -> Vec<u8> {
+    leaves.sort();
+    leaves.dedup();
+
+    merkle_root(&leaves)
 }
 
 ///
-/// Create a set of BigUint of each files.
-/// They are calculated from its contents and path.
+/// Create a set of Leaf of each files.
+/// Paths can be glob patterns such as `**/Cargo.toml`, which match files found under the directory before the first wildcard.
 ///
 /// ```rust
-/// use famo_hash::read;
+/// use famo_hash::{read, Options, Symlinks};
 ///
-/// let paths = vec!["Cargo.toml", "Cargo.lock", "target"];
-/// let vals = read(&paths).unwrap();
+/// let paths = vec!["Cargo.toml", "src/*.rs"];
+/// let options = Options {
+///     symlinks: Symlinks::Link,
+///     ..Default::default()
+/// };
+/// let leaves = read(&paths, &options).unwrap();
 /// ```
 ///
-pub fn read(paths: &Vec<&str>) -> Result<Vec<BigUint>, Error> {
-    let mut v: Vec<BigUint> = vec![];
+pub fn read(paths: &Vec<&str>, options: &Options) -> Result<Vec<Leaf>, Error> {
+    let mut walker = Walker::new(options)?;
 
     for path in paths.iter() {
-        let path = Path::new(path);
+        let found = if filter::is_glob(path) {
+            walker.glob(path)?
+        } else {
+            walker.literal(Path::new(path))?
+        };
 
-        if path.is_file() {
-            read_file(path, &mut v)?;
-        } else if path.is_dir() {
-            read_dir(path, &mut v)?;
+        if found == 0 {
+            match options.missing {
+                Missing::Error => {
+                    return Err(HashError::NoMatch {
+                        pattern: path.to_string(),
+                    }
+                    .into())
+                }
+                Missing::Warn => warn!("'{}' matches nothing to be watched.", path),
+            }
         }
     }
 
-    Ok(v)
+    Ok(walker.leaves)
 }
 
 ///
-/// Recursively read directory and create a set of BigUint of each files.
-/// The result is pushed into Vec<BigUint>.
+/// Recursively read directory and create a Leaf of each files.
+/// Entries are read in the order of their names and the result is pushed into Vec<Leaf>.
+/// Entries which can't be read and symbolic links making cycles are errors.
 ///
 /// ```rust
-/// extern crate num; // Use external crate 'num'
 /// extern crate famo_hash;
 ///
 /// // If you use extern famo_lib, this line should be
-/// // `use famo_lib::hash::read_dir;`
-/// use famo_hash::read_dir;
-/// use num::bigint::BigUint;
+/// // `use famo_lib::hash::{read_dir, Leaf};`
+/// use famo_hash::{read_dir, Leaf};
 /// use std::path::Path;
 ///
-/// let mut v: Vec<BigUint> = vec![];
+/// let mut v: Vec<Leaf> = vec![];
 /// let path = Path::new("src");
-/// read_dir(&path, &mut v).unwrap();
+/// read_dir(&path, &Default::default(), &mut v).unwrap();
 /// ```
 ///
-pub fn read_dir(path: &Path, v: &mut Vec<BigUint>) -> Result<(), Error> {
-    for file_or_dir in path.read_dir()? {
-        if let Ok(file_or_dir) = file_or_dir {
-            let file_or_dir = file_or_dir.path();
+pub fn read_dir(path: &Path, options: &Options, v: &mut Vec<Leaf>) -> Result<(), Error> {
+    let mut walker = Walker::new(options)?;
+    walker.walk_dir(path)?;
+
+    v.append(&mut walker.leaves);
+
+    Ok(())
+}
+
+struct Walker<'a> {
+    options: &'a Options,
+    filter: Filter,
+    // Canonical paths of directories being read, which are revisited only by cycles.
+    ancestors: Vec<PathBuf>,
+    // The glob pattern being matched, whose entries are decided before they're read.
+    pattern: Option<Pattern>,
+    leaves: Vec<Leaf>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(options: &'a Options) -> Result<Walker<'a>, Error> {
+        Ok(Walker {
+            options,
+            filter: Filter::new(options)?,
+            ancestors: vec![],
+            pattern: None,
+            leaves: vec![],
+        })
+    }
+
+    // Paths given explicitly are read even if `.gitignore` ignores them.
+    fn literal(&mut self, path: &Path) -> Result<usize, Error> {
+        let start = self.leaves.len();
+
+        match fs::symlink_metadata(path) {
+            Ok(_) if self.filter.is_excluded(&normalize(path)?) => {}
+            Ok(_) => self.walk(path)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(unreadable(path, &e).into()),
+        }
+
+        Ok(self.leaves.len() - start)
+    }
+
+    fn glob(&mut self, pattern: &str) -> Result<usize, Error> {
+        let base: PathBuf = Path::new(pattern)
+            .components()
+            .take_while(|c| !c.as_os_str().to_str().is_some_and(filter::is_glob))
+            .collect();
+        let base = if base.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            base
+        };
+
+        let start = self.leaves.len();
+
+        if base.is_dir() {
+            self.pattern = Some(Pattern::new(pattern)?);
+            let walked = self.walk_dir(&base);
+            self.pattern = None;
+
+            walked?;
+        }
+
+        Ok(self.leaves.len() - start)
+    }
+
+    fn walk(&mut self, path: &Path) -> Result<(), Error> {
+        // Entries which neither match the pattern nor contain matches are skipped before they're read.
+        let (matched, entered) = match self.pattern {
+            Some(ref pattern) => {
+                let path = normalize(path)?;
+                (pattern.is_match(&path), pattern.may_contain(&path))
+            }
+            None => (true, true),
+        };
+
+        if !matched && !entered {
+            return Ok(());
+        }
+
+        let mut metadata = fs::symlink_metadata(path).map_err(|e| unreadable(path, &e))?;
 
-            if file_or_dir.is_file() {
-                read_file(file_or_dir.as_path(), v)?;
-            } else if file_or_dir.is_dir() {
-                read_dir(file_or_dir.as_path(), v)?;
+        if metadata.file_type().is_symlink() {
+            if self.options.symlinks == Symlinks::Link {
+                return if matched {
+                    read_link(path, &mut self.leaves)
+                } else {
+                    Ok(())
+                };
             }
+
+            metadata = fs::metadata(path).map_err(|e| unreadable(path, &e))?;
+        }
+
+        if metadata.is_file() && matched {
+            read_file(path, &mut self.leaves)
+        } else if metadata.is_dir() && entered {
+            self.walk_dir(path)
+        } else {
+            // Sockets, FIFOs and devices have no contents to be hashed.
+            Ok(())
         }
     }
 
-    Ok(())
+    fn walk_dir(&mut self, path: &Path) -> Result<(), Error> {
+        let canonical = fs::canonicalize(path).map_err(|e| unreadable(path, &e))?;
+
+        if self.ancestors.contains(&canonical) {
+            return Err(HashError::SymlinkCycle {
+                path: path.display().to_string(),
+                target: canonical.display().to_string(),
+            }
+            .into());
+        }
+
+        let mut entries = path
+            .read_dir()
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| unreadable(path, &e))?;
+
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let entered = self.filter.enter(path)?;
+        self.ancestors.push(canonical);
+
+        for entry in entries {
+            let entry = entry.path();
+            let is_dir = entry.is_dir();
+
+            if self.filter.is_excluded(&normalize(&entry)?)
+                || self.filter.is_ignored(&entry, is_dir)
+            {
+                continue;
+            }
+
+            self.walk(&entry)?;
+        }
+
+        self.ancestors.pop();
+        self.filter.leave(entered);
+
+        Ok(())
+    }
 }
 
 ///
-/// Read a single file to create a unique BigUint.
-/// The value is created from its contents and path.
-/// The result is pushed into Vec<BigUint>.
+/// Read a single file to create a Leaf.
+/// The result is pushed into Vec<Leaf>.
 ///
 /// ```rust
-/// extern crate num; // Use external crate 'num'
 /// extern crate famo_hash;
 ///
 /// // If you use extern famo_lib, this line should be
-/// // `use famo_lib::hash::read_file;`
-/// use famo_hash::read_file;
-/// use num::bigint::BigUint;
+/// // `use famo_lib::hash::{read_file, Leaf};`
+/// use famo_hash::{read_file, Leaf};
 /// use std::path::Path;
 ///
-/// let mut v: Vec<BigUint> = vec![];
+/// let mut v: Vec<Leaf> = vec![];
 /// let path = Path::new("Cargo.toml");
 /// read_file(&path, &mut v).unwrap();
+/// assert_eq!(v[0].path(), "Cargo.toml");
 /// ```
 ///
-pub fn read_file(path: &Path, v: &mut Vec<BigUint>) -> Result<(), Error> {
-    let c = unique_contents(path)?;
-    let n = gen_biguint(c.as_slice());
+pub fn read_file(path: &Path, v: &mut Vec<Leaf>) -> Result<(), Error> {
+    let mut hasher = Sha256::new();
+    hasher.input(&fs::read(path).map_err(|e| unreadable(path, &e))?);
 
-    v.push(n);
+    v.push(Leaf {
+        path: normalize(path)?,
+        kind: FILE,
+        digest: hasher.result().to_vec(),
+    });
 
     Ok(())
 }
 
-// Path -> Vec<u8>
-fn unique_contents(path: &Path) -> Result<Vec<u8>, Error> {
-    let mut contents = fs::read(path)?;
-    contents.append(&mut path.to_str().unwrap().as_bytes().to_vec());
+///
+/// Read a symbolic link to create a Leaf from the path it points to, without reading the target.
+/// The result is pushed into Vec<Leaf>.
+///
+pub fn read_link(path: &Path, v: &mut Vec<Leaf>) -> Result<(), Error> {
+    let target = fs::read_link(path).map_err(|e| unreadable(path, &e))?;
+
+    v.push(Leaf {
+        path: normalize(path)?,
+        kind: LINK,
+        digest: Sha256::digest(normalize(&target)?.as_bytes()).to_vec(),
+    });
 
-    Ok(contents)
+    Ok(())
 }
 
-// &[u8] -> SHA256 -> BigUint
-fn gen_biguint(bytes: &[u8]) -> BigUint {
-    let mut hasher = Sha256::new();
-    hasher.input(bytes);
-    let bytes: &[u8] = &hasher.result();
-    let n = BigUint::from_bytes_be(bytes);
+///
+/// Calculate the root of the Merkle tree of leaves in the order.
+/// A node is SHA256(NODE || left || right) and a node without its pair is carried to the upper level as is.
+///
+pub fn merkle_root(leaves: &[Leaf]) -> Vec<u8> {
+    let mut level: Vec<Vec<u8>> = leaves.iter().map(|leaf| leaf.hash()).collect();
+
+    if level.is_empty() {
+        return Sha256::digest(&[NODE]).to_vec();
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.input([NODE]);
+                    hasher.input(left);
+                    hasher.input(right);
+                    hasher.result().to_vec()
+                }
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
 
-    n
+    level.remove(0)
+}
+
+// `./src//lib.rs` and `src/lib.rs` are the same file, separated by `/` on any platform.
+fn normalize(path: &Path) -> Result<String, Error> {
+    let mut components = vec![];
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::RootDir => components.push(""),
+            component => components.push(
+                component
+                    .as_os_str()
+                    .to_str()
+                    .ok_or_else(|| err_msg(format!("{:?} is not valid UTF-8", path)))?,
+            ),
+        }
+    }
+
+    Ok(components.join("/"))
+}
+
+fn unreadable(path: &Path, e: &io::Error) -> HashError {
+    HashError::Unreadable {
+        path: path.display().to_string(),
+        description: e.to_string(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn leaf(path: &str, contents: &str) -> Leaf {
+        Leaf {
+            path: path.to_owned(),
+            kind: FILE,
+            digest: Sha256::digest(contents.as_bytes()).to_vec(),
+        }
+    }
+
+    #[test]
+    fn hex_has_fixed_length_and_version() {
+        let dir = "fixtures/unique_contents_are_not_same_if_paths_are_different";
+
+        let file = "fixtures/unique_contents_of_single_file/file";
+
+        for paths in &[vec![], vec![dir], vec![file]] {
+            let h = hex(paths, &Options::default()).unwrap();
+
+            assert!(h.starts_with("v1-"));
+            assert_eq!(h.len(), 3 + 64);
+        }
+    }
+
+    #[test]
+    fn hex_doesnt_depend_on_order_and_way_to_specify() {
+        let file0 = "fixtures/unique_contents_are_not_same_if_paths_are_different/file0";
+        let file1 = "./fixtures/unique_contents_are_not_same_if_paths_are_different/file1";
+        let dir = "fixtures/unique_contents_are_not_same_if_paths_are_different/";
+
+        let h = hex(&vec![file0, file1], &Options::default()).unwrap();
+
+        assert_eq!(h, hex(&vec![file1, file0], &Options::default()).unwrap());
+        assert_eq!(h, hex(&vec![dir], &Options::default()).unwrap());
+        assert_eq!(h, hex(&vec![dir, file0], &Options::default()).unwrap());
+    }
+
+    #[test]
+    fn merkle_root_depends_on_pairs_of_paths_and_contents() {
+        let root = merkle_root(&[leaf("a", "x"), leaf("b", "y")]);
+
+        assert_ne!(root, merkle_root(&[leaf("a", "y"), leaf("b", "x")]));
+        assert_ne!(root, merkle_root(&[leaf("b", "y"), leaf("a", "x")]));
+        assert_ne!(
+            root,
+            merkle_root(&[leaf("a", "x"), leaf("b", "y"), leaf("b", "y")])
+        );
+        assert_ne!(root, merkle_root(&[leaf("a", "x")]));
+    }
+
     #[test]
-    fn gen_biguint_success() {
-        let string = "famo".to_owned();
-        let n = gen_biguint(string.as_bytes());
+    fn merkle_root_depends_on_kinds_of_leaves() {
+        let link = Leaf {
+            kind: LINK,
+            ..leaf("a", "x")
+        };
+
+        assert_ne!(merkle_root(&[leaf("a", "x")]), merkle_root(&[link]));
+    }
+
+    #[cfg(unix)]
+    fn symlink(target: &str, link: &Path) {
+        ::std::os::unix::fs::symlink(target, link).unwrap();
+    }
+
+    #[test]
+    fn read_dir_in_order_of_names() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for name in &["b", "c", "a"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let mut v = vec![];
+        read_dir(dir.path(), &Options::default(), &mut v).unwrap();
+
+        let names: Vec<&str> = v
+            .iter()
+            .map(|leaf| leaf.path().rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_hashed_by_policy() {
+        let link_options = Options {
+            symlinks: Symlinks::Link,
+            ..Default::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file"), "contents").unwrap();
+        symlink("file", &dir.path().join("link"));
+
+        let path = dir.path().to_str().unwrap();
+        let follow = read(&vec![path], &Options::default()).unwrap();
+        let link = read(&vec![path], &link_options).unwrap();
+
+        assert!(!follow[1].is_link());
+        assert_eq!(follow[0].digest(), follow[1].digest());
+        assert!(link[1].is_link());
+        assert_eq!(link[1].digest(), &Sha256::digest(b"file")[..]);
+
+        // Changing the target changes the link only when it's followed.
+        fs::write(dir.path().join("file"), "changed").unwrap();
 
+        assert_ne!(follow, read(&vec![path], &Options::default()).unwrap());
+        assert_eq!(link[1], read(&vec![path], &link_options).unwrap()[1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycles_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        symlink("..", &dir.path().join("sub").join("parent"));
+
+        let path = dir.path().to_str().unwrap();
+        let e = read(&vec![path], &Options::default()).unwrap_err();
+
+        match e.downcast_ref::<HashError>() {
+            Some(HashError::SymlinkCycle { path, .. }) => assert!(path.ends_with("sub/parent")),
+            _ => panic!("unexpected error: {}", e),
+        }
+
+        let link_options = Options {
+            symlinks: Symlinks::Link,
+            ..Default::default()
+        };
+        assert_eq!(read(&vec![path], &link_options).unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_entries_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        symlink("missing", &dir.path().join("dangling"));
+
+        let path = dir.path().to_str().unwrap();
+        let e = read(&vec![path], &Options::default()).unwrap_err();
+
+        match e.downcast_ref::<HashError>() {
+            Some(HashError::Unreadable { path, .. }) => assert!(path.ends_with("dangling")),
+            _ => panic!("unexpected error: {}", e),
+        }
+    }
+
+    fn tree(files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        for file in files {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+
+        dir
+    }
+
+    fn paths(dir: &Path, patterns: &[&str], options: &Options) -> Vec<String> {
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|p| format!("{}/{}", dir.display(), p))
+            .collect();
+        let patterns: Vec<&str> = patterns.iter().map(|p| p.as_str()).collect();
+        let prefix = format!("{}/", dir.display());
+
+        read(&patterns, options)
+            .unwrap()
+            .iter()
+            .map(|leaf| leaf.path().trim_start_matches(&prefix).to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn glob_patterns_match_files() {
+        let dir = tree(&[
+            "a/Cargo.toml",
+            "b/c/Cargo.toml",
+            "b/c/main.rs",
+            "Cargo.toml",
+        ]);
+        let options = Options::default();
+
+        assert_eq!(
+            paths(dir.path(), &["**/Cargo.toml"], &options),
+            vec!["Cargo.toml", "a/Cargo.toml", "b/c/Cargo.toml"]
+        );
         assert_eq!(
-            "70685181624748995639890562460754138405737812120862994921776424960744339326042"
-                .to_owned(),
-            format!("{}", n)
+            paths(dir.path(), &["*/Cargo.toml"], &options),
+            vec!["a/Cargo.toml"]
+        );
+        assert_eq!(
+            paths(dir.path(), &["b/*/*.rs"], &options),
+            vec!["b/c/main.rs"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn glob_patterns_never_read_entries_which_cant_match() {
+        let dir = tree(&["a/Cargo.toml", "a/b/Cargo.toml", "c/Cargo.toml"]);
+        symlink("missing", &dir.path().join("a").join("b").join("dangling"));
+        symlink("missing", &dir.path().join("a").join("dangling"));
+        symlink("missing", &dir.path().join("c").join("dangling"));
+
+        assert_eq!(
+            paths(dir.path(), &["*/Cargo.toml"], &Options::default()),
+            vec!["a/Cargo.toml", "c/Cargo.toml"]
+        );
+        assert_eq!(
+            paths(dir.path(), &["a/*.toml"], &Options::default()),
+            vec!["a/Cargo.toml"]
+        );
+    }
+
+    #[test]
+    fn excluded_paths_are_skipped() {
+        let dir = tree(&["a/Cargo.toml", "b/c/Cargo.toml", "b/Cargo.toml"]);
+        let options = Options {
+            excludes: vec!["**/b/c".to_owned(), "**/a/*.toml".to_owned()],
+            ..Default::default()
+        };
+
+        assert_eq!(paths(dir.path(), &[""], &options), vec!["b/Cargo.toml"]);
+        assert_eq!(
+            paths(dir.path(), &["**/Cargo.toml"], &options),
+            vec!["b/Cargo.toml"]
         );
     }
 
     #[test]
-    fn unique_contents_of_single_file() {
-        let bytes = vec![
-            48, 102, 105, 120, 116, 117, 114, 101, 115, 47,
-            117, 110, 105, 113, 117, 101, 95, 99, 111, 110,
-            116, 101, 110, 116, 115, 95, 111, 102, 95, 115,
-            105, 110, 103, 108, 101, 95, 102, 105, 108, 101,
-            47, 102, 105, 108, 101
-        ];
+    fn gitignore_is_honoured_if_enabled() {
+        let dir = tree(&["kept", "ignored/file", "b/ignored", "b/kept", ".git/HEAD"]);
+        fs::write(dir.path().join(".gitignore"), "ignored\n").unwrap();
+        fs::write(dir.path().join("b").join(".gitignore"), "!ignored\n").unwrap();
 
-        let path = Path::new("fixtures/unique_contents_of_single_file/file");
-        let v = unique_contents(&path).unwrap();
+        let options = Options {
+            gitignore: true,
+            ..Default::default()
+        };
 
-        assert_eq!(v, bytes);
+        assert_eq!(
+            paths(dir.path(), &[""], &options),
+            vec![".gitignore", "b/.gitignore", "b/ignored", "b/kept", "kept"]
+        );
+        assert_eq!(
+            paths(dir.path(), &["ignored/file"], &options),
+            vec!["ignored/file"]
+        );
+        assert_eq!(paths(dir.path(), &[""], &Options::default()).len(), 7);
     }
 
     #[test]
-    fn unique_contents_are_not_same_if_paths_are_different() {
-        let path0 = Path::new("fixtures/unique_contents_are_not_same_if_paths_are_different/file0");
-        let path1 = Path::new("fixtures/unique_contents_are_not_same_if_paths_are_different/file1");
+    fn patterns_matching_nothing() {
+        let dir = tree(&["file"]);
+        let pattern = format!("{}/*.toml", dir.path().display());
 
-        let v0 = unique_contents(&path0).unwrap();
-        let v1 = unique_contents(&path1).unwrap();
+        let e = read(&vec![&pattern], &Options::default()).unwrap_err();
 
-        assert_ne!(v0, v1);
+        match e.downcast_ref::<HashError>() {
+            Some(HashError::NoMatch { pattern: p }) => assert_eq!(p, &pattern),
+            _ => panic!("unexpected error: {}", e),
+        }
+
+        let options = Options {
+            missing: Missing::Warn,
+            ..Default::default()
+        };
+
+        assert!(read(&vec![&pattern, "missing"], &options)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize(Path::new("./src//lib.rs")).unwrap(), "src/lib.rs");
+        assert_eq!(normalize(Path::new("/tmp/./file")).unwrap(), "/tmp/file");
     }
 }
@@ -0,0 +1,51 @@
+use clap::ArgMatches;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum Auth {
+    #[default]
+    Anonymous,
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+#[derive(Clone, Default, Debug, Getters, Setters)]
+pub struct Context {
+    #[get = "pub"]
+    url: String,
+    #[get = "pub"]
+    #[set = "pub"]
+    auth: Auth,
+}
+
+impl Context {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            auth: Auth::default(),
+        }
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let url = matches.value_of("http_url").unwrap();
+
+        let mut context = Self::new(url);
+
+        if let Some(token) = matches.value_of("http_token") {
+            context.set_auth(Auth::Bearer {
+                token: token.to_owned(),
+            });
+        } else if let Some(user) = matches.value_of("http_user") {
+            context.set_auth(Auth::Basic {
+                user: user.to_owned(),
+                password: matches.value_of("http_password").map(|p| p.to_owned()),
+            });
+        }
+
+        context
+    }
+}
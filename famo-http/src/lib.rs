@@ -0,0 +1,524 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate getset;
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate famo_s3;
+extern crate famo_storage;
+extern crate reqwest;
+extern crate time;
+
+pub mod context;
+mod webdav;
+
+use context::{Auth, Context};
+use failure::Error;
+use famo_s3::{request, uri_decode, uri_encode};
+use famo_storage::{Entry, Storage};
+use reqwest::{Body, Method, RequestBuilder, Response, StatusCode, Url};
+use std::io::Read;
+
+#[derive(Debug, Fail)]
+enum HttpError {
+    #[fail(display = "Error response from HTTP server ({})", reason)]
+    General { reason: String },
+}
+
+///
+/// Store caches on an HTTP server by plain `HEAD`/`GET`/`PUT`/`DELETE` under a base URL.
+/// Listing caches requires WebDAV (`PROPFIND`).
+///
+#[derive(Getters)]
+pub struct HttpStorage {
+    #[get = "pub"]
+    context: Context,
+}
+
+impl HttpStorage {
+    pub fn new(context: Context) -> Self {
+        Self { context }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.context.url(), uri_encode(key, false))
+    }
+
+    fn request(&self, method: Method, url: &str) -> Result<RequestBuilder, Error> {
+        debug!("{} {}", method, url);
+
+        let builder = request()?.request(method, url);
+
+        let builder = match self.context.auth() {
+            Auth::Anonymous => builder,
+            Auth::Basic { user, password } => builder.basic_auth(user, password.as_ref()),
+            Auth::Bearer { token } => builder.bearer_auth(token),
+        };
+
+        Ok(builder)
+    }
+
+    // WebDAV servers reject PUT to missing collections, while other servers create them.
+    // Failures are left to the following PUT.
+    fn make_collections(&self, key: &str) -> Result<(), Error> {
+        let method = Method::from_bytes(b"MKCOL")?;
+        let mut collection = String::new();
+
+        for segment in key
+            .split('/')
+            .rev()
+            .skip(1)
+            .collect::<Vec<&str>>()
+            .into_iter()
+            .rev()
+        {
+            collection = collection + segment + "/";
+
+            let response = self
+                .request(method.clone(), &self.url(&collection))?
+                .send()?;
+
+            debug!("MKCOL {}: {}", collection, response.status());
+        }
+
+        Ok(())
+    }
+
+    // Keys of the resources under the collection whose keys start with the prefix.
+    fn list_collection(
+        &self,
+        collection: &str,
+        prefix: &str,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        let response = self
+            .request(Method::from_bytes(b"PROPFIND")?, &self.url(collection))?
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(webdav::PROPFIND_BODY)
+            .send()?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+
+        let body = success(response, "PROPFIND")?.text()?;
+        let base_path = Url::parse(self.context.url())?
+            .path()
+            .trim_end_matches('/')
+            .to_owned();
+
+        for resource in webdav::resources(&body) {
+            // Hrefs are either absolute paths or absolute URLs.
+            let path = match Url::parse(&resource.href) {
+                Ok(url) => url.path().to_owned(),
+                Err(_) => resource.href.clone(),
+            };
+            let key = match uri_decode(&path).strip_prefix(&(base_path.clone() + "/")) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+
+            if resource.collection {
+                // The collection itself is listed in the response.
+                if key.trim_end_matches('/') == collection.trim_end_matches('/') {
+                    continue;
+                }
+
+                let key = key.trim_end_matches('/').to_owned() + "/";
+
+                if key.starts_with(prefix) || prefix.starts_with(&key) {
+                    self.list_collection(&key, prefix, entries)?;
+                }
+
+                continue;
+            }
+
+            if let (true, Some(size), Some(last_modified)) = (
+                key.starts_with(prefix),
+                resource.size,
+                resource.last_modified,
+            ) {
+                entries.push(Entry::new(&key, size, last_modified));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for HttpStorage {
+    fn name(&self) -> &str {
+        "HTTP server"
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        let response = self.request(Method::HEAD, &self.url(key))?.send()?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(false),
+            status if status.is_success() => Ok(true),
+            _ => success(response, "HEAD").map(|_| false),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let response = self.request(Method::GET, &self.url(key))?.send()?;
+
+        Ok(Box::new(success(response, "GET")?))
+    }
+
+    fn put(&self, key: &str, reader: Box<dyn Read + Send>, len: u64) -> Result<(), Error> {
+        self.make_collections(key)?;
+
+        let response = self
+            .request(Method::PUT, &self.url(key))?
+            .header("Content-Type", "application/octet-stream")
+            .header("Content-Length", len)
+            .body(Body::sized(reader, len))
+            .send()?;
+
+        success(response, "PUT")?;
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let response = self.request(Method::DELETE, &self.url(key))?.send()?;
+
+        success(response, "DELETE")?;
+
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Entry>, Error> {
+        let collection = match prefix.rfind('/') {
+            Some(i) => &prefix[..=i],
+            None => "",
+        };
+        let mut entries = vec![];
+
+        self.list_collection(collection, prefix, &mut entries)?;
+
+        entries.sort_by(|a, b| a.key().cmp(b.key()));
+
+        Ok(entries)
+    }
+}
+
+fn success(mut response: Response, operation: &str) -> Result<Response, Error> {
+    if !response.status().is_success() {
+        return Err(HttpError::General {
+            reason: format!(
+                "{}: {} ({})",
+                operation,
+                response.text()?,
+                response.status().as_u16()
+            ),
+        }.into());
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+    type Collections = Arc<Mutex<BTreeSet<String>>>;
+
+    // A stand-in of WebDAV servers which keeps objects in memory.
+    // Collections are made by MKCOL, and PUT into missing collections is rejected as WebDAV servers do.
+    struct StandIn {
+        url: String,
+        objects: Objects,
+        collections: Collections,
+    }
+
+    impl StandIn {
+        fn start(authorization: Option<&'static str>) -> StandIn {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/dav", listener.local_addr().unwrap());
+            let objects: Objects = Arc::default();
+            let collections: Collections = Arc::default();
+            let shared = (objects.clone(), collections.clone());
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let (objects, collections) = shared.clone();
+                    thread::spawn(move || {
+                        serve(stream.unwrap(), &objects, &collections, authorization)
+                    });
+                }
+            });
+
+            StandIn {
+                url,
+                objects,
+                collections,
+            }
+        }
+    }
+
+    // The collection containing the resource, which is "" for the root.
+    fn parent(key: &str) -> &str {
+        key.trim_end_matches('/')
+            .rsplit_once('/')
+            .map_or("", |(parent, _)| parent)
+    }
+
+    fn serve(
+        stream: TcpStream,
+        objects: &Objects,
+        collections: &Collections,
+        authorization: Option<&str>,
+    ) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+
+        loop {
+            let mut line = String::new();
+
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap().to_owned();
+            let path = uri_decode(parts.next().unwrap());
+            let mut headers = BTreeMap::new();
+
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+
+                if header.trim().is_empty() {
+                    break;
+                }
+
+                let (name, value) = header.split_at(header.find(':').unwrap());
+                headers.insert(name.to_lowercase(), value[1..].trim().to_owned());
+            }
+
+            let mut body = vec![
+                0;
+                headers
+                    .get("content-length")
+                    .map_or(0, |len| len.parse().unwrap())
+            ];
+            reader.read_exact(&mut body).unwrap();
+
+            let key = path.trim_start_matches("/dav/").to_owned();
+            let mut objects = objects.lock().unwrap();
+            let mut collections = collections.lock().unwrap();
+            let exists = |collections: &BTreeSet<String>, collection: &str| {
+                collection.is_empty() || collections.contains(collection)
+            };
+
+            let (status, content) = if authorization.is_some()
+                && headers.get("authorization").map(|a| a.as_str()) != authorization
+            {
+                ("401 Unauthorized", vec![])
+            } else {
+                match method.as_str() {
+                    "HEAD" | "GET" => match objects.get(&key) {
+                        Some(object) => ("200 OK", object.clone()),
+                        None => ("404 Not Found", vec![]),
+                    },
+                    "PUT" if !exists(&collections, parent(&key)) => ("409 Conflict", vec![]),
+                    "PUT" => {
+                        objects.insert(key, body);
+                        ("201 Created", vec![])
+                    }
+                    "DELETE" => match objects.remove(&key) {
+                        Some(_) => ("204 No Content", vec![]),
+                        None => ("404 Not Found", vec![]),
+                    },
+                    "MKCOL" if exists(&collections, key.trim_end_matches('/')) => {
+                        ("405 Method Not Allowed", vec![])
+                    }
+                    "MKCOL" if !exists(&collections, parent(&key)) => ("409 Conflict", vec![]),
+                    "MKCOL" => {
+                        collections.insert(key.trim_end_matches('/').to_owned());
+                        ("201 Created", vec![])
+                    }
+                    "PROPFIND" => ("207 Multi-Status", multistatus(&objects, &key).into_bytes()),
+                    _ => ("405 Method Not Allowed", vec![]),
+                }
+            };
+
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                status,
+                content.len()
+            )
+            .unwrap();
+
+            if method != "HEAD" {
+                stream.write_all(&content).unwrap();
+            }
+        }
+    }
+
+    // Children of the collection at depth 1.
+    fn multistatus(objects: &BTreeMap<String, Vec<u8>>, collection: &str) -> String {
+        let mut responses = format!(
+            "<D:response><D:href>/dav/{}</D:href><D:propstat><D:prop>\
+             <D:resourcetype><D:collection/></D:resourcetype></D:prop></D:propstat></D:response>",
+            collection
+        );
+        let mut collections = vec![];
+
+        for (key, object) in objects.range(collection.to_owned()..) {
+            let name = match key.strip_prefix(collection) {
+                Some(name) => name,
+                None => break,
+            };
+
+            match name.find('/') {
+                Some(i) => collections.push(name[..=i].to_owned()),
+                None => {
+                    responses += &format!(
+                        "<D:response><D:href>/dav/{}{}</D:href><D:propstat><D:prop><D:resourcetype/>\
+                         <D:getcontentlength>{}</D:getcontentlength>\
+                         <D:getlastmodified>Sun, 06 Nov 1994 08:49:37 GMT</D:getlastmodified>\
+                         </D:prop></D:propstat></D:response>",
+                        uri_encode(collection, false),
+                        uri_encode(name, false),
+                        object.len()
+                    );
+                }
+            }
+        }
+
+        collections.dedup();
+
+        for name in collections {
+            responses += &format!(
+                "<D:response><D:href>/dav/{}{}</D:href><D:propstat><D:prop>\
+                 <D:resourcetype><D:collection/></D:resourcetype></D:prop></D:propstat></D:response>",
+                collection, name
+            );
+        }
+
+        format!(
+            "<?xml version=\"1.0\"?><D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+            responses
+        )
+    }
+
+    fn put(storage: &HttpStorage, key: &str, data: &'static [u8]) {
+        storage.put(key, Box::new(data), data.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn put_get_and_delete() {
+        let stand_in = StandIn::start(Some("Basic ZmFtbzpzZWNyZXQ="));
+        let mut context = Context::new(&stand_in.url);
+        context.set_auth(Auth::Basic {
+            user: "famo".to_owned(),
+            password: Some("secret".to_owned()),
+        });
+        let storage = HttpStorage::new(context);
+
+        assert!(!storage.exists("famo/hex").unwrap());
+
+        put(&storage, "famo/hex", b"cache");
+        assert!(storage.exists("famo/hex").unwrap());
+        assert_eq!(stand_in.objects.lock().unwrap()["famo/hex"], b"cache");
+
+        let mut data = vec![];
+        storage
+            .get("famo/hex")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"cache");
+
+        storage.delete("famo/hex").unwrap();
+        assert!(!storage.exists("famo/hex").unwrap());
+        assert!(storage.get("famo/hex").is_err());
+    }
+
+    #[test]
+    fn nested_keys_are_put_into_collections_made_before() {
+        let stand_in = StandIn::start(None);
+        let storage = HttpStorage::new(Context::new(&stand_in.url));
+
+        // The stand-in rejects PUT into missing collections.
+        let response = storage
+            .request(Method::PUT, &storage.url("a/b/c/hex"))
+            .unwrap()
+            .body("cache")
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        put(&storage, "a/b/c/hex", b"cache");
+        put(&storage, "a/b/other", b"other");
+
+        let mut data = vec![];
+        storage
+            .get("a/b/c/hex")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"cache");
+        assert_eq!(
+            *stand_in.collections.lock().unwrap(),
+            ["a", "a/b", "a/b/c"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect()
+        );
+    }
+
+    #[test]
+    fn unauthorized_requests_fail() {
+        let stand_in = StandIn::start(Some("Bearer token"));
+        let mut context = Context::new(&stand_in.url);
+
+        assert!(HttpStorage::new(context.clone()).exists("hex").is_err());
+
+        context.set_auth(Auth::Bearer {
+            token: "token".to_owned(),
+        });
+        assert!(!HttpStorage::new(context).exists("hex").unwrap());
+    }
+
+    #[test]
+    fn list_by_propfind() {
+        let stand_in = StandIn::start(None);
+        let storage = HttpStorage::new(Context::new(&stand_in.url));
+
+        put(&storage, "a/master/0 1", b"0");
+        put(&storage, "a/master/1", b"11");
+        put(&storage, "a/feature/2", b"222");
+        put(&storage, "b/3", b"3333");
+
+        let keys = storage
+            .list("a/ma")
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.key().to_owned(), *entry.size()))
+            .collect::<Vec<(String, u64)>>();
+
+        assert_eq!(
+            keys,
+            vec![("a/master/0 1".to_owned(), 1), ("a/master/1".to_owned(), 2)]
+        );
+        assert_eq!(storage.list("").unwrap().len(), 4);
+        assert!(storage.list("c/").unwrap().is_empty());
+    }
+}
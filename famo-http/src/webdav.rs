@@ -0,0 +1,139 @@
+// Tiny helpers to read the multistatus responses of PROPFIND.
+// Elements are matched by their local names, since servers choose their own namespace prefixes.
+use famo_s3::xml;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///
+/// A resource in a multistatus response.
+///
+#[derive(Debug, PartialEq)]
+pub struct Resource {
+    pub href: String,
+    pub collection: bool,
+    pub size: Option<u64>,
+    pub last_modified: Option<SystemTime>,
+}
+
+pub const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                                 <D:propfind xmlns:D=\"DAV:\"><D:prop>\
+                                 <D:resourcetype/><D:getcontentlength/><D:getlastmodified/>\
+                                 </D:prop></D:propfind>";
+
+pub fn resources(multistatus: &str) -> Vec<Resource> {
+    elements(multistatus, "response")
+        .into_iter()
+        .filter_map(|response| {
+            let href = elements(response, "href").into_iter().next()?;
+
+            Some(Resource {
+                href: xml::unescape(href.trim()),
+                collection: !elements(response, "collection").is_empty(),
+                size: elements(response, "getcontentlength")
+                    .into_iter()
+                    .next()
+                    .and_then(|size| size.trim().parse().ok()),
+                last_modified: elements(response, "getlastmodified")
+                    .into_iter()
+                    .next()
+                    .and_then(|date| parse_http_date(date.trim())),
+            })
+        })
+        .collect()
+}
+
+// Contents of the elements of the local name. Empty elements have empty contents.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut contents = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        let qualified_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('/') || local_name(qualified_name) != name {
+            continue;
+        }
+
+        if tag.ends_with('/') {
+            contents.push("");
+            continue;
+        }
+
+        let close = format!("</{}>", qualified_name);
+
+        match rest.find(&close) {
+            Some(end) => {
+                contents.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    contents
+}
+
+fn local_name(qualified_name: &str) -> &str {
+    qualified_name.rsplit(':').next().unwrap_or(qualified_name)
+}
+
+// Sun, 06 Nov 1994 08:49:37 GMT
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let tm = ::time::strptime(date, "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    let seconds = tm.to_timespec().sec;
+
+    if seconds < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resources_of_multistatus() {
+        let multistatus = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                           <D:multistatus xmlns:D=\"DAV:\">\
+                           <D:response><D:href>/dav/famo/</D:href><D:propstat><D:prop>\
+                           <D:resourcetype><D:collection/></D:resourcetype>\
+                           </D:prop></D:propstat></D:response>\
+                           <D:response><D:href>/dav/famo/a&amp;b</D:href><D:propstat><D:prop>\
+                           <D:resourcetype/>\
+                           <D:getcontentlength>132</D:getcontentlength>\
+                           <lp1:getlastmodified xmlns:lp1=\"DAV:\">Sun, 06 Nov 1994 08:49:37 GMT</lp1:getlastmodified>\
+                           </D:prop></D:propstat></D:response>\
+                           </D:multistatus>";
+
+        assert_eq!(
+            resources(multistatus),
+            vec![
+                Resource {
+                    href: "/dav/famo/".to_owned(),
+                    collection: true,
+                    size: None,
+                    last_modified: None,
+                },
+                Resource {
+                    href: "/dav/famo/a&b".to_owned(),
+                    collection: false,
+                    size: Some(132),
+                    last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+                },
+            ]
+        );
+    }
+}
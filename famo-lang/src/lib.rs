@@ -13,6 +13,12 @@ pub struct Lang {
     build: &'static str,
     #[get = "pub"]
     command: &'static str,
+    // Commands whose outputs identify the toolchain, such as `rustc -vV`.
+    #[get = "pub"]
+    probes: Vec<&'static str>,
+    // Environment variables which change the outputs of the build.
+    #[get = "pub"]
+    envs: Vec<&'static str>,
 }
 
 impl Lang {
@@ -21,16 +27,20 @@ impl Lang {
         watches: Vec<&'static str>,
         build: &'static str,
         command: &'static str,
+        probes: Vec<&'static str>,
+        envs: Vec<&'static str>,
     ) -> Lang {
         Lang {
             name,
             watches,
             build,
             command,
+            probes,
+            envs,
         }
     }
 
-    fn is_it(&self, path: &AsRef<Path>) -> bool {
+    fn is_it(&self, path: &dyn AsRef<Path>) -> bool {
         for f in self.watches.iter() {
             let mut path_buf = PathBuf::new();
             path_buf.push(path);
@@ -52,35 +62,45 @@ pub fn langs() -> Vec<Lang> {
             vec!["Cargo.toml", "Cargo.lock"],
             "target",
             "cargo build",
+            vec!["rustc -vV", "cargo -V"],
+            vec!["RUSTFLAGS", "CARGO_BUILD_TARGET"],
         ),
         Lang::new(
             "yarn",
             vec!["package.json", "yarn.lock"],
             "node_modules",
             "yarn build",
+            vec!["node --version", "yarn --version"],
+            vec!["NODE_ENV"],
         ),
         Lang::new(
             "node_js",
             vec!["package.json", "package-lock.json"],
             "node_modules",
             "npm build",
+            vec!["node --version", "npm --version"],
+            vec!["NODE_ENV"],
         ),
         Lang::new(
             "ruby",
             vec!["Gemfile", "Gemfile.lock"],
             "vendor",
             "bundle install --path vendor/bundle",
+            vec!["ruby --version", "bundle --version"],
+            vec!["BUNDLE_WITHOUT"],
         ),
         Lang::new(
             "crystal",
             vec!["shard.yaml", "shard.lock"],
             "lib",
             "shards build",
+            vec!["crystal --version"],
+            vec![],
         ),
     ]
 }
 
-pub fn detect(path: &AsRef<Path>) -> Option<Lang> {
+pub fn detect(path: &dyn AsRef<Path>) -> Option<Lang> {
     for l in langs().iter() {
         if l.is_it(path) {
             return Some(l.clone());
@@ -103,6 +123,8 @@ mod test {
         assert_eq!(lang.watches(), &vec!["Cargo.toml", "Cargo.lock"]);
         assert_eq!(lang.build(), &"target");
         assert_eq!(lang.command(), &"cargo build");
+        assert_eq!(lang.probes(), &vec!["rustc -vV", "cargo -V"]);
+        assert_eq!(lang.envs(), &vec!["RUSTFLAGS", "CARGO_BUILD_TARGET"]);
     }
 
     #[test]
@@ -114,6 +136,7 @@ mod test {
         assert_eq!(lang.watches(), &vec!["package.json", "package-lock.json"]);
         assert_eq!(lang.build(), &"node_modules");
         assert_eq!(lang.command(), &"npm build");
+        assert_eq!(lang.probes(), &vec!["node --version", "npm --version"]);
     }
 
     #[test]
@@ -1,4 +1,8 @@
 pub extern crate famo_archive as archive;
+pub extern crate famo_crypt as crypt;
 pub extern crate famo_hash as hash;
+pub extern crate famo_http as http;
 pub extern crate famo_lang as lang;
+pub extern crate famo_redis as redis;
 pub extern crate famo_s3 as s3;
+pub extern crate famo_storage as storage;
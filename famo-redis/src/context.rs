@@ -0,0 +1,52 @@
+use clap::ArgMatches;
+use failure::Error;
+
+#[derive(Clone, Default, Debug, Getters, Setters)]
+pub struct Context {
+    #[get = "pub"]
+    url: String,
+    #[get = "pub"]
+    #[set = "pub"]
+    ttl: u64,
+    #[get = "pub"]
+    #[set = "pub"]
+    chunk_size: u64,
+}
+
+/// Default lifetime of caches in seconds. (7 days)
+pub const DEFAULT_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// Default size of each chunk stored as a value. (8 MiB)
+pub const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+impl Context {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            ttl: DEFAULT_TTL,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self, Error> {
+        let url = matches.value_of("redis_url").unwrap();
+
+        let mut context = Self::new(url);
+
+        if matches.is_present("redis_ttl") {
+            let ttl = value_t!(matches, "redis_ttl", u64).unwrap_or_else(|e| e.exit());
+            context.set_ttl(ttl);
+        }
+
+        if matches.is_present("redis_chunk_size") {
+            let chunk_size =
+                value_t!(matches, "redis_chunk_size", u64).unwrap_or_else(|e| e.exit());
+            let chunk_size = chunk_size.checked_mul(1024 * 1024).ok_or_else(|| {
+                format_err!("{} MiB is too large. (--redis_chunk_size)", chunk_size)
+            })?;
+            context.set_chunk_size(chunk_size);
+        }
+
+        Ok(context)
+    }
+}
@@ -0,0 +1,698 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate getset;
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate famo_storage;
+extern crate redis;
+
+pub mod context;
+
+use context::Context;
+use failure::Error;
+use famo_storage::{Entry, Storage};
+use redis::{Client, Connection};
+use std::io::{self, Read};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Chunks of an object are stored at `{key}{CHUNKS}{upload id}:{index}`.
+const CHUNKS: &str = ":chunks:";
+
+/// Lifetime of chunks of replaced objects in seconds, which are still read by `get` started before. (10 minutes)
+const GRACE_TTL: u64 = 10 * 60;
+
+#[derive(Debug, Fail)]
+enum RedisError {
+    #[fail(display = "'{}' is not found", key)]
+    NotFound { key: String },
+    #[fail(display = "Chunk {} of '{}' is missing (expired?)", index, key)]
+    MissingChunk { key: String, index: usize },
+    #[fail(
+        display = "{} bytes are read for '{}' while {} bytes are expected",
+        read, key, len
+    )]
+    UnexpectedLength { key: String, read: u64, len: u64 },
+}
+
+///
+/// Store caches in Redis.
+/// An object is split into chunks of `context.chunk_size()` bytes, since Redis limits the size of values.
+/// Metadata of the object is stored as a hash at the key and published after all of the chunks,
+/// so that readers never see a partially written object.
+///
+#[derive(Getters)]
+pub struct RedisStorage {
+    #[get = "pub"]
+    context: Context,
+    client: Client,
+}
+
+// Metadata stored at the key of an object.
+struct Meta {
+    upload: String,
+    chunks: usize,
+    size: u64,
+    last_modified: u64,
+}
+
+impl RedisStorage {
+    pub fn new(context: Context) -> Result<Self, Error> {
+        let client = Client::open(context.url().as_str())?;
+
+        Ok(Self { context, client })
+    }
+
+    fn connection(&self) -> Result<Connection, Error> {
+        self.client.get_connection().map_err(Into::into)
+    }
+
+    fn meta(&self, connection: &Connection, key: &str) -> Result<Option<Meta>, Error> {
+        let (upload, chunks, size, last_modified): (
+            Option<String>,
+            Option<usize>,
+            Option<u64>,
+            Option<u64>,
+        ) = redis::cmd("HMGET")
+            .arg(key)
+            .arg(&["upload", "chunks", "size", "last_modified"])
+            .query(connection)?;
+
+        match (upload, chunks, size, last_modified) {
+            (Some(upload), Some(chunks), Some(size), Some(last_modified)) => Ok(Some(Meta {
+                upload,
+                chunks,
+                size,
+                last_modified,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    // Store the chunks and return the number of them and the total size.
+    // Stored chunks are deleted when any of them fails.
+    fn put_chunks(
+        &self,
+        connection: &Connection,
+        key: &str,
+        upload: &str,
+        reader: &mut dyn Read,
+    ) -> Result<(usize, u64), Error> {
+        let (mut chunks, mut size) = (0, 0);
+
+        loop {
+            match self.put_chunk(connection, key, upload, chunks, reader) {
+                Ok(0) => return Ok((chunks, size)),
+                Ok(stored) => {
+                    chunks += 1;
+                    size += stored;
+                }
+                Err(e) => {
+                    if let Err(delete_error) = self.delete_chunks(connection, key, upload, chunks) {
+                        warn!("Failed to delete chunks of '{}': {}", key, delete_error);
+                    }
+
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    // Store the next chunk read from the reader and return its size.
+    fn put_chunk(
+        &self,
+        connection: &Connection,
+        key: &str,
+        upload: &str,
+        index: usize,
+        reader: &mut dyn Read,
+    ) -> Result<u64, Error> {
+        let chunk_size = (*self.context.chunk_size()).max(1);
+        // The buffer grows with the data read, since the tail of the object is usually smaller than a chunk.
+        let mut data = Vec::new();
+        reader.take(chunk_size).read_to_end(&mut data)?;
+
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let mut set = redis::cmd("SET");
+        set.arg(chunk_key(key, upload, index)).arg(&data[..]);
+
+        if *self.context.ttl() > 0 {
+            set.arg("EX").arg(*self.context.ttl());
+        }
+
+        set.query::<()>(connection)?;
+
+        debug!(
+            "Chunk {} ({} bytes) of '{}' is stored",
+            index,
+            data.len(),
+            key
+        );
+
+        Ok(data.len() as u64)
+    }
+
+    // Replace the metadata and refresh the TTL of the chunks at once.
+    fn publish(&self, connection: &Connection, key: &str, meta: &Meta) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+
+        pipe.atomic()
+            .cmd("DEL")
+            .arg(key)
+            .ignore()
+            .cmd("HSET")
+            .arg(key)
+            .arg("upload")
+            .arg(&meta.upload)
+            .arg("chunks")
+            .arg(meta.chunks)
+            .arg("size")
+            .arg(meta.size)
+            .arg("last_modified")
+            .arg(meta.last_modified)
+            .ignore();
+
+        if *self.context.ttl() > 0 {
+            pipe.cmd("EXPIRE")
+                .arg(key)
+                .arg(*self.context.ttl())
+                .ignore();
+
+            for index in 0..meta.chunks {
+                pipe.cmd("EXPIRE")
+                    .arg(chunk_key(key, &meta.upload, index))
+                    .arg(*self.context.ttl())
+                    .ignore();
+            }
+        }
+
+        pipe.query::<()>(connection)?;
+
+        Ok(())
+    }
+
+    fn expire_chunks(
+        &self,
+        connection: &Connection,
+        key: &str,
+        upload: &str,
+        chunks: usize,
+    ) -> Result<(), Error> {
+        if chunks == 0 {
+            return Ok(());
+        }
+
+        let ttl = match *self.context.ttl() {
+            0 => GRACE_TTL,
+            ttl => ttl.min(GRACE_TTL),
+        };
+        let mut pipe = redis::pipe();
+
+        for index in 0..chunks {
+            pipe.cmd("EXPIRE")
+                .arg(chunk_key(key, upload, index))
+                .arg(ttl)
+                .ignore();
+        }
+
+        pipe.query::<()>(connection)?;
+
+        Ok(())
+    }
+
+    fn delete_chunks(
+        &self,
+        connection: &Connection,
+        key: &str,
+        upload: &str,
+        chunks: usize,
+    ) -> Result<(), Error> {
+        if chunks == 0 {
+            return Ok(());
+        }
+
+        let keys = (0..chunks)
+            .map(|index| chunk_key(key, upload, index))
+            .collect::<Vec<String>>();
+
+        redis::cmd("DEL").arg(keys).query::<()>(connection)?;
+
+        Ok(())
+    }
+}
+
+impl Storage for RedisStorage {
+    fn name(&self) -> &str {
+        "Redis"
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.meta(&self.connection()?, key)?.is_some())
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let connection = self.connection()?;
+        let meta = match self.meta(&connection, key)? {
+            Some(meta) => meta,
+            None => {
+                return Err(RedisError::NotFound {
+                    key: key.to_owned(),
+                }.into())
+            }
+        };
+        let key = key.to_owned();
+
+        Ok(Box::new(ChunkReader::new(meta.chunks, move |index| {
+            let data: Option<Vec<u8>> = redis::cmd("GET")
+                .arg(chunk_key(&key, &meta.upload, index))
+                .query(&connection)?;
+
+            data.ok_or_else(|| {
+                RedisError::MissingChunk {
+                    key: key.clone(),
+                    index,
+                }.into()
+            })
+        })))
+    }
+
+    fn put(&self, key: &str, mut reader: Box<dyn Read + Send>, len: u64) -> Result<(), Error> {
+        let connection = self.connection()?;
+        let previous = self.meta(&connection, key)?;
+
+        // Chunks are never shared between uploads, so that concurrent uploads of the same key
+        // don't corrupt each other.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let upload = format!("{}-{}", process::id(), now.as_nanos());
+
+        let (chunks, size) = self.put_chunks(&connection, key, &upload, &mut reader)?;
+
+        let meta = Meta {
+            upload,
+            chunks,
+            size,
+            last_modified: now.as_secs(),
+        };
+
+        if size != len {
+            self.delete_chunks(&connection, key, &meta.upload, chunks)?;
+
+            return Err(RedisError::UnexpectedLength {
+                key: key.to_owned(),
+                read: size,
+                len,
+            }.into());
+        }
+
+        self.publish(&connection, key, &meta)?;
+
+        // Previous chunks expire soon instead of being deleted, since concurrent readers may be reading them.
+        if let Some(previous) = previous {
+            if let Err(e) = self.expire_chunks(&connection, key, &previous.upload, previous.chunks)
+            {
+                warn!("Failed to expire previous chunks of '{}': {}", key, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let connection = self.connection()?;
+
+        if let Some(meta) = self.meta(&connection, key)? {
+            redis::cmd("DEL").arg(key).query::<()>(&connection)?;
+            self.delete_chunks(&connection, key, &meta.upload, meta.chunks)?;
+        }
+
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Entry>, Error> {
+        let connection = self.connection()?;
+        let keys = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("MATCH")
+            .arg(format!("{}*", escape_pattern(prefix)))
+            .iter::<String>(&connection)?
+            .filter(|key| !key.contains(CHUNKS))
+            .collect::<Vec<String>>();
+
+        let mut entries = vec![];
+
+        for key in keys {
+            // Keys not stored by famo are not objects.
+            if let Ok(Some(meta)) = self.meta(&connection, &key) {
+                let last_modified = UNIX_EPOCH + Duration::from_secs(meta.last_modified);
+                entries.push(Entry::new(&key, meta.size, last_modified));
+            }
+        }
+
+        entries.sort_by(|a, b| a.key().cmp(b.key()));
+
+        Ok(entries)
+    }
+}
+
+fn chunk_key(key: &str, upload: &str, index: usize) -> String {
+    format!("{}{}{}:{}", key, CHUNKS, upload, index)
+}
+
+// Escape glob characters of the pattern of SCAN.
+fn escape_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if let '*' | '?' | '[' | ']' | '\\' = c {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+///
+/// A reader which concatenates chunks fetched one by one.
+///
+pub struct ChunkReader<F> {
+    chunks: usize,
+    next: usize,
+    buffer: Vec<u8>,
+    position: usize,
+    fetch: F,
+}
+
+impl<F> ChunkReader<F>
+where
+    F: FnMut(usize) -> Result<Vec<u8>, Error>,
+{
+    pub fn new(chunks: usize, fetch: F) -> Self {
+        ChunkReader {
+            chunks,
+            next: 0,
+            buffer: vec![],
+            position: 0,
+            fetch,
+        }
+    }
+}
+
+impl<F> Read for ChunkReader<F>
+where
+    F: FnMut(usize) -> Result<Vec<u8>, Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.buffer.len() {
+            if self.next >= self.chunks {
+                return Ok(0);
+            }
+
+            self.buffer = (self.fetch)(self.next).map_err(|e| io::Error::other(e.to_string()))?;
+            self.position = 0;
+            self.next += 1;
+        }
+
+        let n = (&self.buffer[self.position..]).read(buf)?;
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+    #[derive(Default)]
+    struct Data {
+        strings: HashMap<Vec<u8>, Vec<u8>>,
+        hashes: HashMap<Vec<u8>, Fields>,
+        ttls: HashMap<Vec<u8>, u64>,
+    }
+
+    enum Reply {
+        Status(&'static str),
+        Integer(usize),
+        Bulk(Option<Vec<u8>>),
+        Array(Vec<Reply>),
+    }
+
+    impl Reply {
+        fn write(&self, w: &mut dyn Write) {
+            match self {
+                Reply::Status(status) => write!(w, "+{}\r\n", status).unwrap(),
+                Reply::Integer(n) => write!(w, ":{}\r\n", n).unwrap(),
+                Reply::Bulk(None) => write!(w, "$-1\r\n").unwrap(),
+                Reply::Bulk(Some(value)) => {
+                    write!(w, "${}\r\n", value.len()).unwrap();
+                    w.write_all(value).unwrap();
+                    write!(w, "\r\n").unwrap();
+                }
+                Reply::Array(replies) => {
+                    write!(w, "*{}\r\n", replies.len()).unwrap();
+
+                    for reply in replies {
+                        reply.write(w);
+                    }
+                }
+            }
+        }
+    }
+
+    // A stand-in of Redis which keeps values in memory and supports the commands famo sends.
+    // TTLs are recorded but never expire values.
+    fn stand_in() -> (String, Arc<Mutex<Data>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let data: Arc<Mutex<Data>> = Arc::default();
+        let shared = data.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let data = shared.clone();
+                thread::spawn(move || serve(stream.unwrap(), &data));
+            }
+        });
+
+        (url, data)
+    }
+
+    fn serve(stream: TcpStream, data: &Mutex<Data>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+
+        while let Some(command) = read_command(&mut reader) {
+            let reply = match (command[0].to_ascii_uppercase().as_slice(), &mut queued) {
+                (b"MULTI", _) => {
+                    queued = Some(vec![]);
+                    Reply::Status("OK")
+                }
+                (b"EXEC", _) => Reply::Array(
+                    queued
+                        .take()
+                        .unwrap()
+                        .iter()
+                        .map(|command| execute(command, &mut data.lock().unwrap()))
+                        .collect(),
+                ),
+                (_, Some(commands)) => {
+                    commands.push(command);
+                    Reply::Status("QUEUED")
+                }
+                (_, None) => execute(&command, &mut data.lock().unwrap()),
+            };
+
+            reply.write(&mut stream);
+        }
+    }
+
+    fn read_command(reader: &mut dyn BufRead) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return None;
+        }
+
+        let len = line.trim()[1..].parse::<usize>().unwrap();
+        let mut args = vec![];
+
+        for _ in 0..len {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+
+            let mut arg = vec![0; line.trim()[1..].parse::<usize>().unwrap() + 2];
+            reader.read_exact(&mut arg).unwrap();
+            arg.truncate(arg.len() - 2);
+            args.push(arg);
+        }
+
+        Some(args)
+    }
+
+    fn execute(command: &[Vec<u8>], data: &mut Data) -> Reply {
+        let key = command[1].clone();
+
+        match command[0].to_ascii_uppercase().as_slice() {
+            b"GET" => Reply::Bulk(data.strings.get(&key).cloned()),
+            b"SET" => {
+                data.strings.insert(key.clone(), command[2].clone());
+
+                if command.len() > 4 {
+                    let ttl = String::from_utf8_lossy(&command[4]).parse().unwrap();
+                    data.ttls.insert(key, ttl);
+                }
+
+                Reply::Status("OK")
+            }
+            b"HSET" => {
+                let fields = command[2..]
+                    .chunks(2)
+                    .map(|field| (field[0].clone(), field[1].clone()))
+                    .collect::<Fields>();
+                let len = fields.len();
+
+                data.hashes.insert(key, fields);
+                Reply::Integer(len)
+            }
+            b"HMGET" => {
+                let fields = data.hashes.get(&key).cloned().unwrap_or_default();
+
+                Reply::Array(
+                    command[2..]
+                        .iter()
+                        .map(|name| {
+                            Reply::Bulk(
+                                fields
+                                    .iter()
+                                    .find(|(field, _)| field == name)
+                                    .map(|(_, value)| value.clone()),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+            b"EXPIRE" => {
+                let ttl = String::from_utf8_lossy(&command[2]).parse().unwrap();
+                data.ttls.insert(key, ttl);
+                Reply::Integer(1)
+            }
+            b"DEL" => {
+                let deleted = command[1..]
+                    .iter()
+                    .filter(|key| {
+                        data.ttls.remove(*key);
+                        data.strings.remove(*key).is_some() || data.hashes.remove(*key).is_some()
+                    })
+                    .count();
+
+                Reply::Integer(deleted)
+            }
+            _ => panic!("Unknown command {:?}", String::from_utf8_lossy(&command[0])),
+        }
+    }
+
+    #[test]
+    fn replaced_objects_stay_readable_by_concurrent_gets() {
+        let (url, data) = stand_in();
+        let mut context = Context::new(&url);
+        context.set_chunk_size(4);
+        let storage = RedisStorage::new(context).unwrap();
+
+        storage
+            .put("famo/hex", Box::new(&b"first cache"[..]), 11)
+            .unwrap();
+
+        let mut reader = storage.get("famo/hex").unwrap();
+        let mut head = [0; 4];
+        reader.read_exact(&mut head).unwrap();
+
+        storage
+            .put("famo/hex", Box::new(&b"second cache"[..]), 12)
+            .unwrap();
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(
+            format!("{}{}", String::from_utf8_lossy(&head), rest),
+            "first cache"
+        );
+
+        let mut read = String::new();
+        storage
+            .get("famo/hex")
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, "second cache");
+
+        let data = data.lock().unwrap();
+        let expiring = data
+            .strings
+            .keys()
+            .filter(|key| data.ttls.get(*key) == Some(&GRACE_TTL))
+            .count();
+        assert_eq!(expiring, 3);
+    }
+
+    #[test]
+    fn chunk_reader_concatenates_chunks() {
+        let chunks = [b"famo".to_vec(), vec![], b" cache".to_vec()];
+        let mut reader = ChunkReader::new(chunks.len(), |index| Ok(chunks[index].clone()));
+
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+
+        assert_eq!(read, "famo cache");
+    }
+
+    #[test]
+    fn chunk_reader_reports_missing_chunks() {
+        let mut reader = ChunkReader::new(3, |index| {
+            if index == 1 {
+                Err(RedisError::MissingChunk {
+                    key: "hex".to_owned(),
+                    index,
+                }.into())
+            } else {
+                Ok(vec![0; 4])
+            }
+        });
+
+        let mut read = vec![];
+        let e = reader.read_to_end(&mut read).unwrap_err();
+
+        assert!(e.to_string().contains("Chunk 1 of 'hex' is missing"));
+        assert_eq!(read.len(), 4);
+    }
+
+    #[test]
+    fn chunk_keys_are_not_listed_as_objects() {
+        assert_eq!(chunk_key("famo/hex", "1-2", 3), "famo/hex:chunks:1-2:3");
+        assert!(chunk_key("famo/hex", "1-2", 3).contains(CHUNKS));
+    }
+
+    #[test]
+    fn escape_glob_characters() {
+        assert_eq!(escape_pattern("famo/hex"), "famo/hex");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+}
@@ -1,55 +1,407 @@
 use clap::ArgMatches;
+use credentials;
+use failure::Error;
+use signature::{uri_encode, SignatureVersion};
+use std::str::FromStr;
 
-#[derive(Default, Debug, Getters)]
+///
+/// How buckets are addressed in URLs.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Addressing {
+    /// `{endpoint}/{bucket}/{key}`, which S3 compatibles such as MinIO support.
+    #[default]
+    Path,
+    /// `{bucket}.{endpoint}/{key}`, which is required by buckets in newer regions of AWS.
+    Virtual,
+}
+
+impl FromStr for Addressing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "path" => Ok(Addressing::Path),
+            "virtual" => Ok(Addressing::Virtual),
+            _ => Err(format_err!("Unknown addressing style '{}'", s)),
+        }
+    }
+}
+
+///
+/// Server-side encryption of objects written by famo.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerSideEncryption {
+    /// SSE-S3 by keys managed by S3. (`AES256`)
+    S3,
+    /// SSE-KMS by the key of KMS, or the default key of the account if it's not given. (`aws:kms`)
+    Kms { key_id: Option<String> },
+    /// SSE-C by the key given by customers (32 bytes in base64), which every request of the data has to send.
+    Customer { key: String },
+}
+
+impl FromStr for ServerSideEncryption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "AES256" => Ok(ServerSideEncryption::S3),
+            "aws:kms" => Ok(ServerSideEncryption::Kms { key_id: None }),
+            _ => Err(format_err!("Unknown server-side encryption '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, Getters, Setters)]
 pub struct Context {
     #[get = "pub"]
     region: String,
     #[get = "pub"]
     endpoint: String,
     #[get = "pub"]
+    #[set = "pub"]
+    scheme: String,
+    #[get = "pub"]
+    #[set = "pub"]
+    port: Option<u16>,
+    #[get = "pub"]
+    #[set = "pub"]
+    addressing: Addressing,
+    #[get = "pub"]
     access_key_id: String,
     #[get = "pub"]
     secret_access_key: String,
     #[get = "pub"]
+    #[set = "pub"]
+    session_token: Option<String>,
+    #[get = "pub"]
     bucket: String,
     #[get = "pub"]
-    key: Option<String>,
+    #[set = "pub"]
+    signature_version: SignatureVersion,
+    #[get = "pub"]
+    #[set = "pub"]
+    part_size: u64,
+    #[get = "pub"]
+    #[set = "pub"]
+    concurrency: usize,
+    #[get = "pub"]
+    #[set = "pub"]
+    server_side_encryption: Option<ServerSideEncryption>,
+    #[get = "pub"]
+    #[set = "pub"]
+    storage_class: Option<String>,
+    /// Tags of objects in the order. (`key=value`)
+    #[get = "pub"]
+    #[set = "pub"]
+    tagging: Vec<(String, String)>,
+    /// Canned ACL of objects, such as `bucket-owner-full-control`.
+    #[get = "pub"]
+    #[set = "pub"]
+    acl: Option<String>,
 }
 
+/// Default size of each part of multipart uploads. (64 MiB)
+pub const DEFAULT_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default number of parts transferred at the same time.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
 impl Context {
+    ///
+    /// `endpoint` may have a scheme and a port such as `http://localhost:9000`. (`https` by default)
+    ///
     pub fn new(
         region: &str,
         endpoint: &str,
         access_key_id: &str,
         secret_access_key: &str,
         bucket: &str,
-        key: Option<&str>,
     ) -> Self {
+        let (scheme, endpoint) = match endpoint.find("://") {
+            Some(i) => (&endpoint[..i], &endpoint[i + 3..]),
+            None => ("https", endpoint),
+        };
+        let endpoint = endpoint.trim_end_matches('/');
+
+        // Endpoints whose ports aren't numbers are kept as they are and fail to be resolved.
+        let (endpoint, port) = match endpoint.rfind(':') {
+            Some(i) => match endpoint[i + 1..].parse() {
+                Ok(port) => (&endpoint[..i], Some(port)),
+                Err(_) => (endpoint, None),
+            },
+            None => (endpoint, None),
+        };
+
         Self {
             region: region.to_owned(),
             endpoint: endpoint.to_owned(),
+            scheme: scheme.to_owned(),
+            port,
+            addressing: Addressing::default(),
             access_key_id: access_key_id.to_owned(),
             secret_access_key: secret_access_key.to_owned(),
+            session_token: None,
             bucket: bucket.to_owned(),
-            key: key.map(|k| k.to_owned()),
+            signature_version: SignatureVersion::default(),
+            part_size: DEFAULT_PART_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            server_side_encryption: None,
+            storage_class: None,
+            tagging: vec![],
+            acl: None,
+        }
+    }
+
+    ///
+    /// Headers of requests creating objects. (Put Object and Create Multipart Upload)
+    ///
+    pub fn write_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![];
+
+        match self.server_side_encryption {
+            Some(ServerSideEncryption::S3) => {
+                headers.push(("x-amz-server-side-encryption", "AES256".to_owned()));
+            }
+            Some(ServerSideEncryption::Kms { ref key_id }) => {
+                headers.push(("x-amz-server-side-encryption", "aws:kms".to_owned()));
+
+                if let Some(key_id) = key_id {
+                    headers.push(("x-amz-server-side-encryption-aws-kms-key-id", key_id.clone()));
+                }
+            }
+            Some(ServerSideEncryption::Customer { .. }) => headers.extend(self.customer_key_headers()),
+            None => {}
+        }
+
+        if let Some(ref storage_class) = self.storage_class {
+            headers.push(("x-amz-storage-class", storage_class.clone()));
+        }
+
+        if !self.tagging.is_empty() {
+            let tagging = self
+                .tagging
+                .iter()
+                .map(|(key, value)| format!("{}={}", uri_encode(key, true), uri_encode(value, true)))
+                .collect::<Vec<String>>()
+                .join("&");
+
+            headers.push(("x-amz-tagging", tagging));
+        }
+
+        if let Some(ref acl) = self.acl {
+            headers.push(("x-amz-acl", acl.clone()));
+        }
+
+        headers
+    }
+
+    ///
+    /// Headers of SSE-C, which requests reading and writing the data of objects have to send.
+    /// (Get Object, Head Object, Put Object, Create Multipart Upload and Upload Part)
+    ///
+    pub fn customer_key_headers(&self) -> Vec<(&'static str, String)> {
+        match self.server_side_encryption {
+            Some(ServerSideEncryption::Customer { ref key }) => {
+                let md5 = base64::decode(key)
+                    .map(|key| ::content_md5(&key))
+                    .unwrap_or_default();
+
+                vec![
+                    ("x-amz-server-side-encryption-customer-algorithm", "AES256".to_owned()),
+                    ("x-amz-server-side-encryption-customer-key", key.clone()),
+                    ("x-amz-server-side-encryption-customer-key-md5", md5),
+                ]
+            }
+            _ => vec![],
         }
     }
 
-    pub fn from_matches(matches: &ArgMatches) -> Self {
+    ///
+    /// Host of requests with the port unless it's the default of the scheme.
+    ///
+    pub fn host(&self) -> String {
+        let host = match self.addressing {
+            Addressing::Path => self.endpoint.clone(),
+            Addressing::Virtual => format!("{}.{}", self.bucket, self.endpoint),
+        };
+        let default_port = match self.scheme.as_str() {
+            "http" => Some(80),
+            "https" => Some(443),
+            _ => None,
+        };
+
+        match self.port {
+            Some(port) if Some(port) != default_port => format!("{}:{}", host, port),
+            _ => host,
+        }
+    }
+
+    ///
+    /// Credentials given by options come first, and the standard chain of AWS providers is used otherwise.
+    ///
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self, Error> {
         let region = matches.value_of("region").unwrap();
         let endpoint = matches.value_of("endpoint").unwrap();
-        let access_key_id = matches.value_of("access_key_id").unwrap();
-        let secret_access_key = matches.value_of("secret_access_key").unwrap();
         let bucket = matches.value_of("bucket").unwrap();
-        let key = matches.value_of("key");
 
-        Self::new(
+        let credentials = match (
+            matches.value_of("access_key_id"),
+            matches.value_of("secret_access_key"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => credentials::Credentials::new(
+                access_key_id,
+                secret_access_key,
+                matches.value_of("session_token"),
+            ),
+            (None, None) => credentials::chain()?,
+            // Falling back to other credentials would hide the typo of the other one.
+            _ => {
+                return Err(format_err!(
+                    "--access_key_id and --secret_access_key have to be given together"
+                ))
+            }
+        };
+
+        let mut context = Self::new(
             region,
             endpoint,
-            access_key_id,
-            secret_access_key,
+            credentials.access_key_id(),
+            credentials.secret_access_key(),
             bucket,
-            key,
-        )
+        );
+        context.set_session_token(credentials.session_token().clone());
+
+        if let Some(addressing) = matches.value_of("addressing_style") {
+            context.set_addressing(addressing.parse().unwrap());
+        }
+
+        if let Some(signature_version) = matches.value_of("signature_version") {
+            context.set_signature_version(signature_version.parse().unwrap());
+        }
+
+        if matches.is_present("part_size") {
+            let part_size = value_t!(matches, "part_size", u64).unwrap_or_else(|e| e.exit());
+            let part_size = part_size
+                .checked_mul(1024 * 1024)
+                .ok_or_else(|| format_err!("--part_size is too large"))?;
+            context.set_part_size(part_size);
+        }
+
+        if matches.is_present("concurrency") {
+            let concurrency = value_t!(matches, "concurrency", usize).unwrap_or_else(|e| e.exit());
+            context.set_concurrency(concurrency);
+        }
+
+        context.set_server_side_encryption(server_side_encryption(matches)?);
+        context.set_storage_class(matches.value_of("storage_class").map(|class| class.to_owned()));
+        context.set_acl(matches.value_of("acl").map(|acl| acl.to_owned()));
+
+        if let Some(tags) = matches.values_of("tagging") {
+            let tagging = tags
+                .map(|tag| match tag.find('=') {
+                    Some(i) => (tag[..i].to_owned(), tag[i + 1..].to_owned()),
+                    None => (tag.to_owned(), String::new()),
+                })
+                .collect();
+
+            context.set_tagging(tagging);
+        }
+
+        Ok(context)
+    }
+}
+
+///
+/// Keys of SSE-C are 32 bytes in base64.
+///
+pub fn is_customer_key(key: &str) -> bool {
+    base64::decode(key).map(|key| key.len() == 32).unwrap_or(false)
+}
+
+// `--sse_kms_key_id` implies SSE-KMS and `--sse_customer_key` implies SSE-C.
+fn server_side_encryption(matches: &ArgMatches) -> Result<Option<ServerSideEncryption>, Error> {
+    if let Some(key) = matches.value_of("sse_customer_key") {
+        if !is_customer_key(key) {
+            return Err(format_err!("--sse_customer_key must be 32 bytes in base64"));
+        }
+
+        return Ok(Some(ServerSideEncryption::Customer {
+            key: key.to_owned(),
+        }));
+    }
+
+    if let Some(key_id) = matches.value_of("sse_kms_key_id") {
+        return Ok(Some(ServerSideEncryption::Kms {
+            key_id: Some(key_id.to_owned()),
+        }));
+    }
+
+    match matches.value_of("server_side_encryption") {
+        Some(sse) => Ok(Some(sse.parse()?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context() -> Context {
+        Context::new("us-east-1", "s3.amazonaws.com", "id", "secret", "bucket")
+    }
+
+    #[test]
+    fn write_headers_of_server_side_encryption() {
+        let mut context = context();
+        assert!(context.write_headers().is_empty());
+
+        context.set_server_side_encryption(Some(ServerSideEncryption::Kms {
+            key_id: Some("alias/famo".to_owned()),
+        }));
+        context.set_storage_class(Some("STANDARD_IA".to_owned()));
+
+        assert_eq!(
+            context.write_headers(),
+            vec![
+                ("x-amz-server-side-encryption", "aws:kms".to_owned()),
+                ("x-amz-server-side-encryption-aws-kms-key-id", "alias/famo".to_owned()),
+                ("x-amz-storage-class", "STANDARD_IA".to_owned()),
+            ]
+        );
+        assert!(context.customer_key_headers().is_empty());
+    }
+
+    #[test]
+    fn customer_key_headers_of_sse_c() {
+        let mut context = context();
+        context.set_server_side_encryption(Some(ServerSideEncryption::Customer {
+            key: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
+        }));
+
+        let headers = context.customer_key_headers();
+
+        assert_eq!(headers[0], ("x-amz-server-side-encryption-customer-algorithm", "AES256".to_owned()));
+        assert_eq!(headers[2], ("x-amz-server-side-encryption-customer-key-md5", "cLyPS3KoaSFGi/joRB3OUQ==".to_owned()));
+        assert_eq!(context.write_headers(), headers);
+    }
+
+    #[test]
+    fn tagging_and_acl_headers() {
+        let mut context = context();
+        context.set_tagging(vec![
+            ("team".to_owned(), "build infra".to_owned()),
+            ("ttl".to_owned(), "7d".to_owned()),
+        ]);
+        context.set_acl(Some("bucket-owner-full-control".to_owned()));
+
+        assert_eq!(
+            context.write_headers(),
+            vec![
+                ("x-amz-tagging", "team=build%20infra&ttl=7d".to_owned()),
+                ("x-amz-acl", "bucket-owner-full-control".to_owned()),
+            ]
+        );
     }
 }
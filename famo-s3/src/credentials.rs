@@ -0,0 +1,583 @@
+//
+// The standard chain of AWS credential providers.
+// https://docs.aws.amazon.com/sdkref/latest/guide/standardized-credentials.html
+//
+use failure::Error;
+use reqwest::{Client, Method};
+use serde_json::{self, Value};
+use signature::uri_encode;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use xml;
+
+/// Endpoint of the instance metadata service of EC2.
+pub const INSTANCE_METADATA_ENDPOINT: &str = "http://169.254.169.254";
+
+/// Host of the credentials endpoint of ECS, which `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` is relative to.
+pub const CONTAINER_ENDPOINT: &str = "http://169.254.170.2";
+
+#[derive(Debug, Fail)]
+pub enum CredentialsError {
+    #[fail(
+        display = "No credentials are found. (--access_key_id and --secret_access_key, AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, ~/.aws/credentials, web identity, ECS or EC2 instance metadata)"
+    )]
+    NotFound,
+    #[fail(display = "Failed to get credentials from {} ({})", source, reason)]
+    Failed { source: String, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Credentials {
+    #[get = "pub"]
+    access_key_id: String,
+    #[get = "pub"]
+    secret_access_key: String,
+    #[get = "pub"]
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(access_key_id: &str, secret_access_key: &str, session_token: Option<&str>) -> Self {
+        Self {
+            access_key_id: access_key_id.to_owned(),
+            secret_access_key: secret_access_key.to_owned(),
+            session_token: session_token.map(|token| token.to_owned()),
+        }
+    }
+}
+
+///
+/// Find credentials from environment variables, profiles, web identity tokens,
+/// ECS and the instance metadata of EC2 in the order.
+///
+pub fn chain() -> Result<Credentials, Error> {
+    chain_with(&|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+///
+/// `chain` reading variables by `var` instead of the environment.
+///
+pub fn chain_with(var: &dyn Fn(&str) -> Option<String>) -> Result<Credentials, Error> {
+    if let Some(credentials) = environment(var) {
+        debug!("Credentials are found in environment variables.");
+        return Ok(credentials);
+    }
+
+    if let Some(credentials) = profile(var)? {
+        debug!("Credentials are found in the profile.");
+        return Ok(credentials);
+    }
+
+    if let Some(credentials) = web_identity(var)? {
+        debug!("Credentials are given for the web identity token.");
+        return Ok(credentials);
+    }
+
+    if let Some(credentials) = container(var)? {
+        debug!("Credentials are given by the container credentials endpoint.");
+        return Ok(credentials);
+    }
+
+    if var("AWS_EC2_METADATA_DISABLED").is_none_or(|disabled| disabled != "true") {
+        let endpoint = var("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+            .unwrap_or_else(|| INSTANCE_METADATA_ENDPOINT.to_owned());
+
+        if let Some(credentials) = instance_metadata(&endpoint)? {
+            debug!("Credentials are given by the instance metadata.");
+            return Ok(credentials);
+        }
+    }
+
+    Err(CredentialsError::NotFound.into())
+}
+
+///
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+///
+pub fn environment(var: &dyn Fn(&str) -> Option<String>) -> Option<Credentials> {
+    match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+        (Some(id), Some(secret)) => Some(Credentials::new(
+            &id,
+            &secret,
+            var("AWS_SESSION_TOKEN").as_deref(),
+        )),
+        _ => None,
+    }
+}
+
+///
+/// The profile of `AWS_PROFILE` (`default` by default) in `AWS_SHARED_CREDENTIALS_FILE` (`~/.aws/credentials` by default).
+/// The profile given explicitly must exist.
+///
+pub fn profile(var: &dyn Fn(&str) -> Option<String>) -> Result<Option<Credentials>, Error> {
+    let path = match var("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => match var("HOME").or_else(|| var("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(".aws").join("credentials"),
+            None => return Ok(None),
+        },
+    };
+    let explicit = var("AWS_PROFILE");
+    let name = explicit.clone().unwrap_or_else(|| "default".to_owned());
+
+    let found = match fs::read_to_string(&path) {
+        Ok(ini) => profile_of(&ini, &name),
+        Err(_) => None,
+    };
+
+    if found.is_none() && explicit.is_some() {
+        return Err(CredentialsError::Failed {
+            source: format!("{}", path.display()),
+            reason: format!("The profile '{}' has no credentials", name),
+        }.into());
+    }
+
+    Ok(found)
+}
+
+// [name]
+// aws_access_key_id = ...
+// aws_secret_access_key = ...
+// aws_session_token = ...
+fn profile_of(ini: &str, name: &str) -> Option<Credentials> {
+    let mut section = None;
+    let (mut id, mut secret, mut token) = (None, None, None);
+
+    for line in ini.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = Some(line[1..line.len() - 1].trim().to_owned());
+            continue;
+        }
+
+        if section.as_deref() != Some(name) {
+            continue;
+        }
+
+        if let Some(i) = line.find('=') {
+            let value = line[i + 1..].trim();
+
+            match line[..i].trim() {
+                "aws_access_key_id" => id = Some(value),
+                "aws_secret_access_key" => secret = Some(value),
+                "aws_session_token" => token = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    match (id, secret) {
+        (Some(id), Some(secret)) => Some(Credentials::new(id, secret, token)),
+        _ => None,
+    }
+}
+
+///
+/// AssumeRoleWithWebIdentity of STS for the token in `AWS_WEB_IDENTITY_TOKEN_FILE` and `AWS_ROLE_ARN`.
+/// STS is requested at `AWS_ENDPOINT_URL_STS` if it's given.
+///
+pub fn web_identity(var: &dyn Fn(&str) -> Option<String>) -> Result<Option<Credentials>, Error> {
+    let (token_file, role) = match (var("AWS_WEB_IDENTITY_TOKEN_FILE"), var("AWS_ROLE_ARN")) {
+        (Some(token_file), Some(role)) => (token_file, role),
+        _ => return Ok(None),
+    };
+
+    let failed = |reason: String| CredentialsError::Failed {
+        source: "STS".to_owned(),
+        reason,
+    };
+
+    let token = fs::read_to_string(&token_file)
+        .map_err(|e| failed(format!("Failed to read {}: {}", token_file, e)))?;
+    let endpoint = var("AWS_ENDPOINT_URL_STS").unwrap_or_else(|| match var("AWS_REGION") {
+        Some(region) => format!("https://sts.{}.amazonaws.com", region),
+        None => "https://sts.amazonaws.com".to_owned(),
+    });
+    let session = var("AWS_ROLE_SESSION_NAME").unwrap_or_else(|| "famo".to_owned());
+
+    let url = format!(
+        "{}/?Action=AssumeRoleWithWebIdentity&Version=2011-06-15&RoleArn={}&RoleSessionName={}&WebIdentityToken={}",
+        endpoint.trim_end_matches('/'),
+        uri_encode(&role, true),
+        uri_encode(&session, true),
+        uri_encode(token.trim(), true),
+    );
+
+    let mut response = client()?
+        .get(&url)
+        .send()
+        .map_err(|e| failed(e.to_string()))?;
+    let body = response.text().map_err(|e| failed(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(failed(format!("{}: {}", response.status(), body)).into());
+    }
+
+    let value = |tag| xml::value(&body, tag).map(xml::unescape);
+
+    match (
+        value("AccessKeyId"),
+        value("SecretAccessKey"),
+        value("SessionToken"),
+    ) {
+        (Some(id), Some(secret), token) => {
+            Ok(Some(Credentials::new(&id, &secret, token.as_deref())))
+        }
+        _ => Err(failed(format!("Unexpected response: {}", body)).into()),
+    }
+}
+
+///
+/// The credentials endpoint of ECS at `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` or `AWS_CONTAINER_CREDENTIALS_FULL_URI`.
+///
+pub fn container(var: &dyn Fn(&str) -> Option<String>) -> Result<Option<Credentials>, Error> {
+    let url = match (
+        var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI"),
+        var("AWS_CONTAINER_CREDENTIALS_FULL_URI"),
+    ) {
+        (Some(uri), _) => format!("{}{}", CONTAINER_ENDPOINT, uri),
+        (None, Some(url)) => url,
+        _ => return Ok(None),
+    };
+
+    let mut request = client()?.get(&url);
+
+    if let Some(token) = var("AWS_CONTAINER_AUTHORIZATION_TOKEN") {
+        request = request.header("Authorization", token);
+    }
+
+    let body = fetch(request, "the container credentials endpoint")?;
+
+    credentials_of_json(&body, "the container credentials endpoint").map(Some)
+}
+
+///
+/// The credentials of the IAM role of the EC2 instance, by IMDSv2 and IMDSv1 if tokens aren't available.
+/// Endpoints which can't be connected mean that it's not running on EC2.
+///
+pub fn instance_metadata(endpoint: &str) -> Result<Option<Credentials>, Error> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let client = client()?;
+
+    let token = client
+        .request(Method::PUT, &format!("{}/latest/api/token", endpoint))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "21600")
+        .send();
+
+    let token = match token {
+        Ok(mut response) if response.status().is_success() => Some(response.text()?),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("The instance metadata isn't available: {}", e);
+            return Ok(None);
+        }
+    };
+
+    let get = |path: &str| {
+        let mut request = client.get(&format!(
+            "{}/latest/meta-data/iam/security-credentials/{}",
+            endpoint, path
+        ));
+
+        if let Some(ref token) = token {
+            request = request.header("X-aws-ec2-metadata-token", token.as_str());
+        }
+
+        fetch(request, "the instance metadata")
+    };
+
+    let roles = get("")?;
+    let role = match roles
+        .lines()
+        .map(|role| role.trim())
+        .find(|role| !role.is_empty())
+    {
+        Some(role) => role.to_owned(),
+        None => return Ok(None),
+    };
+
+    credentials_of_json(&get(&role)?, "the instance metadata").map(Some)
+}
+
+// Requests to metadata services are never retried, since they're not available in most environments.
+fn client() -> Result<Client, Error> {
+    let client = Client::builder().timeout(Duration::from_secs(2)).build()?;
+
+    Ok(client)
+}
+
+fn fetch(request: ::reqwest::RequestBuilder, source: &str) -> Result<String, Error> {
+    let failed = |reason: String| CredentialsError::Failed {
+        source: source.to_owned(),
+        reason,
+    };
+
+    let mut response = request.send().map_err(|e| failed(e.to_string()))?;
+    let body = response.text().map_err(|e| failed(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(failed(format!("{}: {}", response.status(), body)).into());
+    }
+
+    Ok(body)
+}
+
+// {"AccessKeyId": "...", "SecretAccessKey": "...", "Token": "...", "Expiration": "..."}
+fn credentials_of_json(body: &str, source: &str) -> Result<Credentials, Error> {
+    let json: Value = serde_json::from_str(body)?;
+    let field = |name: &str| json.get(name).and_then(|value| value.as_str());
+
+    match (field("AccessKeyId"), field("SecretAccessKey")) {
+        (Some(id), Some(secret)) => Ok(Credentials::new(id, secret, field("Token"))),
+        _ => Err(CredentialsError::Failed {
+            source: source.to_owned(),
+            reason: format!("Unexpected response: {}", body),
+        }.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const ROLE: &str = r#"{"Code": "Success", "AccessKeyId": "ASIAEXAMPLE", "SecretAccessKey": "secret", "Token": "token"}"#;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    // A stand-in of the metadata services which responds to requests by `respond(method, path, headers)`.
+    fn stand_in<F>(respond: F) -> String
+    where
+        F: Fn(&str, &str, &HashMap<String, String>) -> (&'static str, String)
+            + Send
+            + Sync
+            + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap().to_owned();
+                let path = parts.next().unwrap().to_owned();
+                let mut headers = HashMap::new();
+
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+
+                    if header.trim().is_empty() {
+                        break;
+                    }
+
+                    let (name, value) = header.split_at(header.find(':').unwrap());
+                    headers.insert(name.to_lowercase(), value[1..].trim().to_owned());
+                }
+
+                let (status, body) = respond(&method, &path, &headers);
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn environment_variables_come_first() {
+        let var = vars(&[
+            ("AWS_ACCESS_KEY_ID", "AKIAEXAMPLE"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("AWS_SESSION_TOKEN", "token"),
+            ("AWS_PROFILE", "missing"),
+        ]);
+
+        assert_eq!(
+            chain_with(&var).unwrap(),
+            Credentials::new("AKIAEXAMPLE", "secret", Some("token"))
+        );
+    }
+
+    #[test]
+    fn profiles_of_credentials_file() {
+        let ini = "[default]\naws_access_key_id = AKIADEFAULT\naws_secret_access_key = default\n\n[ci]\naws_access_key_id=AKIACI\naws_secret_access_key=ci\naws_session_token=token\n";
+
+        assert_eq!(
+            profile_of(ini, "default"),
+            Some(Credentials::new("AKIADEFAULT", "default", None))
+        );
+        assert_eq!(
+            profile_of(ini, "ci"),
+            Some(Credentials::new("AKIACI", "ci", Some("token")))
+        );
+        assert_eq!(profile_of(ini, "missing"), None);
+
+        let var = vars(&[
+            ("AWS_SHARED_CREDENTIALS_FILE", "/nonexistent"),
+            ("AWS_PROFILE", "ci"),
+        ]);
+        assert!(profile(&var).is_err());
+    }
+
+    #[test]
+    fn instance_metadata_by_imdsv2() {
+        let url = stand_in(|method, path, headers| {
+            let authorized =
+                headers.get("x-aws-ec2-metadata-token").map(|t| t.as_str()) == Some("imds-token");
+
+            match (method, path) {
+                ("PUT", "/latest/api/token") => ("200 OK", "imds-token".to_owned()),
+                (_, _) if !authorized => ("401 Unauthorized", String::new()),
+                ("GET", "/latest/meta-data/iam/security-credentials/") => {
+                    ("200 OK", "famo-role\n".to_owned())
+                }
+                ("GET", "/latest/meta-data/iam/security-credentials/famo-role") => {
+                    ("200 OK", ROLE.to_owned())
+                }
+                _ => ("404 Not Found", String::new()),
+            }
+        });
+
+        let var = vars(&[
+            ("AWS_EC2_METADATA_SERVICE_ENDPOINT", &url),
+            ("HOME", "/nonexistent"),
+        ]);
+
+        assert_eq!(
+            chain_with(&var).unwrap(),
+            Credentials::new("ASIAEXAMPLE", "secret", Some("token"))
+        );
+    }
+
+    #[test]
+    fn instance_metadata_by_imdsv1() {
+        let url = stand_in(|method, path, _| match (method, path) {
+            ("GET", "/latest/meta-data/iam/security-credentials/") => {
+                ("200 OK", "famo-role".to_owned())
+            }
+            ("GET", "/latest/meta-data/iam/security-credentials/famo-role") => {
+                ("200 OK", ROLE.to_owned())
+            }
+            _ => ("403 Forbidden", String::new()),
+        });
+
+        assert_eq!(
+            instance_metadata(&url).unwrap(),
+            Some(Credentials::new("ASIAEXAMPLE", "secret", Some("token")))
+        );
+    }
+
+    #[test]
+    fn unavailable_instance_metadata_means_no_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert_eq!(instance_metadata(&url).unwrap(), None);
+
+        let var = vars(&[
+            ("AWS_EC2_METADATA_SERVICE_ENDPOINT", &url),
+            ("HOME", "/nonexistent"),
+        ]);
+        assert!(chain_with(&var).is_err());
+    }
+
+    #[test]
+    fn web_identity_by_sts() {
+        let mut token = tempfile::NamedTempFile::new().unwrap();
+        writeln!(token, "web-token").unwrap();
+
+        let url = stand_in(|method, path, _| {
+            let expected = "/?Action=AssumeRoleWithWebIdentity&Version=2011-06-15\
+                            &RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Ffamo\
+                            &RoleSessionName=famo&WebIdentityToken=web-token";
+
+            match (method, path) {
+                ("GET", path) if path == expected => (
+                    "200 OK",
+                    "<AssumeRoleWithWebIdentityResponse><AssumeRoleWithWebIdentityResult><Credentials>\
+                     <AccessKeyId>ASIASTS</AccessKeyId><SecretAccessKey>a&amp;b</SecretAccessKey>\
+                     <SessionToken>token</SessionToken>\
+                     </Credentials></AssumeRoleWithWebIdentityResult></AssumeRoleWithWebIdentityResponse>"
+                        .to_owned(),
+                ),
+                _ => ("403 Forbidden", "<Error/>".to_owned()),
+            }
+        });
+
+        let var = vars(&[
+            ("AWS_WEB_IDENTITY_TOKEN_FILE", token.path().to_str().unwrap()),
+            ("AWS_ROLE_ARN", "arn:aws:iam::123456789012:role/famo"),
+            ("AWS_ENDPOINT_URL_STS", &url),
+            ("AWS_EC2_METADATA_DISABLED", "true"),
+            ("HOME", "/nonexistent"),
+        ]);
+
+        assert_eq!(
+            chain_with(&var).unwrap(),
+            Credentials::new("ASIASTS", "a&b", Some("token"))
+        );
+
+        let var = vars(&[
+            ("AWS_WEB_IDENTITY_TOKEN_FILE", token.path().to_str().unwrap()),
+            ("AWS_ROLE_ARN", "arn:aws:iam::123456789012:role/other"),
+            ("AWS_ENDPOINT_URL_STS", &url),
+        ]);
+
+        match web_identity(&var).unwrap_err().downcast_ref::<CredentialsError>() {
+            Some(CredentialsError::Failed { source, .. }) => assert_eq!(source, "STS"),
+            _ => panic!("unexpected error"),
+        }
+    }
+
+    #[test]
+    fn container_credentials() {
+        let url = stand_in(|_, path, headers| {
+            match (path, headers.get("authorization").map(|a| a.as_str())) {
+                ("/v2/credentials/famo", Some("container-token")) => ("200 OK", ROLE.to_owned()),
+                _ => ("403 Forbidden", String::new()),
+            }
+        });
+
+        let var = vars(&[
+            (
+                "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+                &format!("{}/v2/credentials/famo", url),
+            ),
+            ("AWS_CONTAINER_AUTHORIZATION_TOKEN", "container-token"),
+            ("AWS_EC2_METADATA_DISABLED", "true"),
+            ("HOME", "/nonexistent"),
+        ]);
+
+        assert_eq!(
+            chain_with(&var).unwrap(),
+            Credentials::new("ASIAEXAMPLE", "secret", Some("token"))
+        );
+    }
+}
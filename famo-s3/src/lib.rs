@@ -1,30 +1,63 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate failure;
 #[macro_use]
 extern crate getset;
 extern crate base64;
+#[macro_use]
 extern crate clap;
 extern crate reqwest;
+extern crate serde_json;
 extern crate time;
 #[macro_use]
 extern crate log;
 extern crate crypto;
+extern crate famo_storage;
+extern crate tempfile;
 
 pub mod context;
+pub mod credentials;
+pub mod multipart;
+pub mod ranged;
 mod signature;
+pub mod storage;
+pub mod xml;
+
+pub use signature::{uri_decode, uri_encode, SignatureVersion};
 
 use context::Context;
+use crypto::digest::Digest;
+use crypto::md5::Md5;
 use failure::Error;
-use reqwest::Response;
-use std::time::Duration;
+use famo_storage::{checksum, Entry, Metadata};
+use reqwest::{Body, RequestBuilder, Response};
+use std::io::{self, Read, Seek};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Metadata of objects which has the hex SHA-256 of them, verified by `download_stream`.
+pub const METADATA_SHA256: &str = "x-amz-meta-famo-sha256";
+
+/// Metadata of objects which has the ID of the key they're encrypted by.
+pub const METADATA_KEY_ID: &str = "x-amz-meta-famo-key-id";
+
+/// How many times a failed request is retried by `retry`.
+const RETRIES: u32 = 3;
 
 #[derive(Debug, Fail)]
 enum S3Error {
     #[fail(display = "Error response from S3 ({})", reason)]
     General { reason: String },
+    #[fail(display = "Error response from S3 ({})", reason)]
+    Status { reason: String, status: u16 },
 }
 
-fn request() -> Result<reqwest::Client, Error> {
+///
+/// HTTP client shared by requests to S3 and other HTTP backends.
+///
+pub fn request() -> Result<reqwest::Client, Error> {
     let client = reqwest::Client::builder()
         .gzip(true)
         .timeout(Duration::from_secs(300))
@@ -33,106 +66,422 @@ fn request() -> Result<reqwest::Client, Error> {
     Ok(client)
 }
 
+fn url(context: &Context, key: &str, query: &str) -> String {
+    let url = format!(
+        "{}://{}{}",
+        context.scheme(),
+        context.host(),
+        signature::object_path(context, key)
+    );
+
+    if query.is_empty() {
+        url
+    } else {
+        format!("{}?{}", url, query)
+    }
+}
+
+// Retry the operation with exponential backoff. (1s, 2s, 4s, ...)
+// Only errors which may pass by themselves are retried, so that bad credentials fail at once.
+fn retry<T, F>(operation: &str, mut f: F) -> Result<T, Error>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match f() {
+            Ok(t) => return Ok(t),
+            Err(e) => {
+                if attempt >= RETRIES || !retryable(&e) {
+                    return Err(e);
+                }
+
+                warn!("{} failed: {} (retrying)", operation, e);
+                thread::sleep(Duration::from_secs(1 << attempt));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// Errors of transports, and responses of server errors (5xx) or throttling (429).
+fn retryable(e: &Error) -> bool {
+    match e.downcast_ref::<S3Error>() {
+        Some(S3Error::Status { status, .. }) => *status >= 500 || *status == 429,
+        Some(S3Error::General { .. }) => false,
+        None => e.downcast_ref::<reqwest::Error>().is_some() || e.downcast_ref::<io::Error>().is_some(),
+    }
+}
+
 pub fn put(context: &Context, key: &str, data: Vec<u8>) -> Result<Response, Error> {
-    let url = format!("https://{}/{}/{}", context.endpoint(), context.bucket(), key);
+    let payload_hash = signature::sha256_hex(&data);
+    let len = data.len() as u64;
+
+    let mut headers = vec![("Content-MD5", content_md5(&data))];
+    headers.extend(checksum_headers(&payload_hash));
+
+    put_body(context, key, Body::from(data), len, &payload_hash, &headers)
+}
+
+///
+/// Put an object by streaming `len` bytes from the reader.
+/// The payload is not hashed since it can be read only once,
+/// but S3 verifies it by the hex SHA-256 of the metadata if it's given.
+///
+pub fn put_stream<R>(
+    context: &Context,
+    key: &str,
+    reader: R,
+    len: u64,
+    metadata: &Metadata,
+) -> Result<Response, Error>
+where
+    R: Read + Send + 'static,
+{
+    put_body(
+        context,
+        key,
+        Body::sized(reader, len),
+        len,
+        signature::UNSIGNED_PAYLOAD,
+        &metadata_headers(metadata),
+    )
+}
+
+fn put_body(
+    context: &Context,
+    key: &str,
+    body: Body,
+    len: u64,
+    payload_hash: &str,
+    headers: &[(&'static str, String)],
+) -> Result<Response, Error> {
+    let url = url(context, key, "");
 
     debug!("Put Object {}", url);
 
-    let date = time::now_utc().rfc822().to_string();
-    let authorization = signature::authorization(
+    let mut headers = headers.to_vec();
+    headers.extend(context.write_headers());
+
+    let headers = signature::sign_with(
         "PUT",
-        &date,
         key,
         "",
-        "",
         "application/octet-stream",
+        &headers,
+        payload_hash,
         context,
-    );
+    )?;
 
-    request()?
-        .put(&url)
-        .header("Date", date)
+    signed(request()?.put(&url), headers)
         .header("Content-Type", "application/octet-stream")
-        .header("Content-Length", data.len() as u64)
-        .header("Authorization", authorization)
-        .body(data)
+        .header("Content-Length", len)
+        .body(body)
         .send()
         .map_err(Into::into)
 }
 
+// S3 rejects bodies which don't match `x-amz-checksum-sha256`,
+// and the metadata is verified after downloading them.
+fn checksum_headers(sha256: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("x-amz-checksum-sha256", base64::encode(&from_hex(sha256))),
+        (METADATA_SHA256, sha256.to_lowercase()),
+    ]
+}
+
+fn metadata_headers(metadata: &Metadata) -> Vec<(&'static str, String)> {
+    let mut headers = vec![];
+
+    if let Some(sha256) = metadata.sha256() {
+        headers.extend(checksum_headers(sha256));
+    }
+
+    if let Some(key_id) = metadata.key_id() {
+        headers.push((METADATA_KEY_ID, key_id.to_owned()));
+    }
+
+    headers
+}
+
+///
+/// Base64 MD5 of the data for `Content-MD5`.
+///
+pub fn content_md5(data: &[u8]) -> String {
+    let mut md5 = Md5::new();
+    let mut digest = [0; 16];
+
+    md5.input(data);
+    md5.result(&mut digest);
+
+    base64::encode(&digest)
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
 pub fn get(context: &Context, key: &str) -> Result<Response, Error> {
-    let url = format!("https://{}/{}/{}", context.endpoint(), context.bucket(), key);
+    let url = url(context, key, "");
 
     debug!("Get Object {}", url);
 
-    let date = time::now_utc().rfc822().to_string();
-    let authorization = signature::authorization("GET", &date, key, "", "", "", context);
+    let headers = signature::sign_with(
+        "GET",
+        key,
+        "",
+        "",
+        &context.customer_key_headers(),
+        signature::EMPTY_PAYLOAD,
+        context,
+    )?;
 
-    request()?
-        .get(&url)
-        .header("Date", date)
-        .header("Authorization", authorization)
+    signed(request()?.get(&url), headers)
         .send()
         .map_err(Into::into)
 }
 
 pub fn get_acl(context: &Context, key: &str) -> Result<Response, Error> {
-    let url = format!("https://{}/{}/{}?acl", context.endpoint(), context.bucket(), key);
+    let url = url(context, key, "acl");
 
     debug!("Get Object ACL {}", url);
 
-    let date = time::now_utc().rfc822().to_string();
-    let authorization = signature::authorization(
-        "GET",
-        &date,
-        &(key.to_owned() + "?acl"),
-        "",
+    let headers = signature::sign("GET", key, "acl", "", signature::EMPTY_PAYLOAD, context)?;
+
+    signed(request()?.get(&url), headers)
+        .send()
+        .map_err(Into::into)
+}
+
+pub fn delete(context: &Context, key: &str) -> Result<Response, Error> {
+    let url = url(context, key, "");
+
+    debug!("Delete Object {}", url);
+
+    let headers = signature::sign("DELETE", key, "", "", signature::EMPTY_PAYLOAD, context)?;
+
+    signed(request()?.delete(&url), headers)
+        .send()
+        .map_err(Into::into)
+}
+
+///
+/// List objects whose keys start with the prefix by ListObjectsV2.
+/// Truncated results are followed by continuation tokens.
+///
+pub fn list_objects(context: &Context, prefix: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = vec![];
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let mut query = format!(
+            "list-type=2&prefix={}",
+            signature::uri_encode(prefix, true)
+        );
+
+        if let Some(token) = continuation_token.take() {
+            query += &format!(
+                "&continuation-token={}",
+                signature::uri_encode(&token, true)
+            );
+        }
+
+        debug!("List Objects {}", url(context, "", &query));
+
+        let headers = signature::sign("GET", "", &query, "", signature::EMPTY_PAYLOAD, context)?;
+        let response = signed(request()?.get(&url(context, "", &query)), headers).send()?;
+        let body = success(response, "List Objects")?.text()?;
+
+        for contents in xml::values(&body, "Contents") {
+            let key = xml::value(contents, "Key").map(xml::unescape);
+            let size = xml::value(contents, "Size").and_then(|size| size.parse().ok());
+            let last_modified = xml::value(contents, "LastModified").and_then(parse_timestamp);
+
+            if let (Some(key), Some(size), Some(last_modified)) = (key, size, last_modified) {
+                entries.push(Entry::new(&key, size, last_modified));
+            }
+        }
+
+        match xml::value(&body, "NextContinuationToken") {
+            Some(token) if xml::value(&body, "IsTruncated") == Some("true") => {
+                continuation_token = Some(xml::unescape(token));
+            }
+            _ => break,
+        }
+    }
+
+    Ok(entries)
+}
+
+// 2009-10-12T17:50:30.000Z
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let tm = time::strptime(timestamp.get(..19)?, "%Y-%m-%dT%H:%M:%S").ok()?;
+    let seconds = tm.to_timespec().sec;
+
+    if seconds < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+fn signed(
+    mut builder: RequestBuilder,
+    headers: Vec<(&'static str, String)>,
+) -> RequestBuilder {
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+
+    builder
+}
+
+pub fn head(context: &Context, key: &str) -> Result<Response, Error> {
+    let url = url(context, key, "");
+
+    debug!("Head Object {}", url);
+
+    let headers = signature::sign_with(
+        "HEAD",
+        key,
         "",
         "",
+        &context.customer_key_headers(),
+        signature::EMPTY_PAYLOAD,
         context,
-    );
+    )?;
 
-    request()?
-        .get(&url)
-        .header("Date", date)
-        .header("Authorization", authorization)
+    signed(request()?.head(&url), headers)
         .send()
         .map_err(Into::into)
 }
 
-pub fn download(context: &Context, key: &str) -> Result<Vec<u8>, Error> {
-    let mut response = get(context, key)?;
-    let mut data: Vec<u8> = Vec::new();
+///
+/// Size of the object in bytes and its hex SHA-256 if it's stored in the metadata.
+///
+pub fn object_info(context: &Context, key: &str) -> Result<(u64, Option<String>), Error> {
+    let response = success(head(context, key)?, "Head Object")?;
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned())
+    };
 
-    if !response.status().is_success() {
-        return Err(S3Error::General {
-            reason: format!(
-                "Get Object: {} ({})",
-                response.text()?,
-                response.status().as_u16()
-            ),
-        }.into());
+    match header("Content-Length").and_then(|len| len.parse().ok()) {
+        Some(len) => Ok((len, header(METADATA_SHA256))),
+        None => Err(S3Error::General {
+            reason: "Head Object: Content-Length is missing".to_owned(),
+        }.into()),
     }
+}
+
+///
+/// Size of the object in bytes.
+///
+pub fn content_length(context: &Context, key: &str) -> Result<u64, Error> {
+    Ok(object_info(context, key)?.0)
+}
 
-    response.copy_to(&mut data)?;
+pub fn download(context: &Context, key: &str) -> Result<Vec<u8>, Error> {
+    let mut reader = download_stream(context, key)?;
+    let mut data: Vec<u8> = Vec::new();
+
+    reader.read_to_end(&mut data)?;
 
     Ok(data)
 }
 
+///
+/// Get an object as a reader so that it can be consumed without buffering it entirely.
+/// Objects larger than `context.part_size()` are downloaded by parallel ranged GETs.
+///
+/// Objects having the SHA-256 in their metadata are spooled to a temporary file and verified,
+/// so that nothing of corrupted objects is read. (`StorageError::Corrupted`)
+///
+pub fn download_stream(context: &Context, key: &str) -> Result<Box<dyn Read + Send>, Error> {
+    let (len, sha256) = object_info(context, key)?;
+
+    let reader: Box<dyn Read + Send> = if len > *context.part_size() {
+        Box::new(ranged::download(context, key, len))
+    } else {
+        Box::new(success(get(context, key)?, "Get Object")?)
+    };
+
+    match sha256 {
+        Some(sha256) => {
+            let mut file = tempfile::tempfile()?;
+            checksum::copy_verified(key, reader, &mut file, len, &sha256)?;
+            file.rewind()?;
+            debug!("SHA-256 of {} is verified", key);
+
+            Ok(Box::new(file))
+        }
+        None => {
+            debug!("{} has no checksum to be verified", key);
+            Ok(reader)
+        }
+    }
+}
+
+///
+/// Upload an object. Objects larger than `context.part_size()` are uploaded by multipart upload.
+///
 pub fn upload(context: &Context, key: &str, data: Vec<u8>) -> Result<(), Error> {
-    let mut response = put(context, key, data)?;
+    let len = data.len() as u64;
+
+    if len > *context.part_size() {
+        let mut metadata = Metadata::default();
+        metadata.set_sha256(Some(signature::sha256_hex(&data)));
+
+        return multipart::upload(context, key, data.as_slice(), len, &metadata);
+    }
+
+    success(put(context, key, data)?, "Put Object")?;
+
+    Ok(())
+}
+
+///
+/// Upload `len` bytes from the reader with the metadata.
+///
+pub fn upload_stream<R>(
+    context: &Context,
+    key: &str,
+    reader: R,
+    len: u64,
+    metadata: &Metadata,
+) -> Result<(), Error>
+where
+    R: Read + Send + 'static,
+{
+    if len > *context.part_size() {
+        return multipart::upload(context, key, reader, len, metadata);
+    }
+
+    success(put_stream(context, key, reader, len, metadata)?, "Put Object")?;
+
+    Ok(())
+}
 
+fn success(mut response: Response, operation: &str) -> Result<Response, Error> {
     if !response.status().is_success() {
-        return Err(S3Error::General {
+        return Err(S3Error::Status {
             reason: format!(
-                "Put Object: {} ({})",
+                "{}: {} ({})",
+                operation,
                 response.text()?,
                 response.status().as_u16()
             ),
+            status: response.status().as_u16(),
         }.into());
     }
 
-    Ok(())
+    Ok(response)
 }
 
 pub fn key_exists(context: &Context, key: &str) -> Result<bool, Error> {
@@ -146,3 +495,95 @@ pub fn key_exists(context: &Context, key: &str) -> Result<bool, Error> {
         }.into()),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use context::Addressing;
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let status = |status| -> Error {
+            S3Error::Status {
+                reason: format!("test ({})", status),
+                status,
+            }.into()
+        };
+
+        assert!(retryable(&status(503)));
+        assert!(retryable(&status(429)));
+        assert!(retryable(&io::Error::new(io::ErrorKind::ConnectionReset, "reset").into()));
+        assert!(!retryable(&status(403)));
+        assert!(!retryable(&status(404)));
+        assert!(!retryable(&format_err!("corrupted")));
+
+        // Errors which are not retried fail at once without waiting.
+        let mut attempts = 0;
+        let result: Result<(), Error> = retry("test", || {
+            attempts += 1;
+            Err(status(403))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn parse_timestamp_of_list_objects() {
+        assert_eq!(
+            parse_timestamp("2009-10-12T17:50:30.000Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_255_369_830))
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn checksums_of_put_object() {
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert_eq!(
+            checksum_headers("2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824"),
+            vec![
+                (
+                    "x-amz-checksum-sha256",
+                    "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_owned()
+                ),
+                (
+                    METADATA_SHA256,
+                    "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn url_encodes_each_segment_of_key() {
+        let context = Context::new("us-east-1", "s3.amazonaws.com", "id", "secret", "bucket");
+
+        assert_eq!(
+            url(&context, "my project/rust/v1-a+b", ""),
+            "https://s3.amazonaws.com/bucket/my%20project/rust/v1-a%2Bb"
+        );
+        assert_eq!(
+            url(&context, "", "list-type=2"),
+            "https://s3.amazonaws.com/bucket/?list-type=2"
+        );
+    }
+
+    #[test]
+    fn url_by_addressing_style_scheme_and_port() {
+        let mut context = Context::new("us-east-1", "s3.amazonaws.com", "id", "secret", "bucket");
+        context.set_addressing(Addressing::Virtual);
+
+        assert_eq!(url(&context, "a b", ""), "https://bucket.s3.amazonaws.com/a%20b");
+
+        let context = Context::new("us-east-1", "http://localhost:9000/", "id", "secret", "bucket");
+
+        assert_eq!(context.endpoint(), "localhost");
+        assert_eq!(context.host(), "localhost:9000");
+        assert_eq!(url(&context, "key", ""), "http://localhost:9000/bucket/key");
+
+        let context = Context::new("us-east-1", "https://minio:443", "id", "secret", "bucket");
+
+        assert_eq!(url(&context, "key", ""), "https://minio/bucket/key");
+    }
+}
@@ -0,0 +1,431 @@
+use super::{content_md5, metadata_headers, request, retry, signed, success, url, S3Error};
+use context::Context;
+use failure::Error;
+use famo_storage::Metadata;
+use signature;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use xml;
+
+/// S3 rejects parts smaller than 5 MiB except for the last one.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// S3 accepts 10,000 parts at most for an upload.
+const MAX_PARTS: u64 = 10_000;
+
+///
+/// Upload `len` bytes from the reader as a multipart upload.
+/// Parts are uploaded by `context.concurrency()` threads and retried individually.
+/// The upload is aborted when any of the parts finally fails.
+/// The metadata is given when the upload is created.
+///
+pub fn upload<R>(
+    context: &Context,
+    key: &str,
+    reader: R,
+    len: u64,
+    metadata: &Metadata,
+) -> Result<(), Error>
+where
+    R: Read,
+{
+    let upload_id = create(context, key, metadata)?;
+    debug!("Multipart upload {} is created", upload_id);
+
+    match upload_parts(context, key, &upload_id, reader, part_size(context, len)) {
+        Ok(etags) => complete(context, key, &upload_id, &etags),
+        Err(e) => {
+            if let Err(abort_error) = abort(context, key, &upload_id) {
+                warn!(
+                    "Failed to abort multipart upload {}: {}",
+                    upload_id, abort_error
+                );
+            }
+
+            Err(e)
+        }
+    }
+}
+
+// Grow the configured part size so that the upload fits into MAX_PARTS.
+fn part_size(context: &Context, len: u64) -> u64 {
+    let part_size = (*context.part_size()).max(MIN_PART_SIZE);
+    part_size.max(len.div_ceil(MAX_PARTS))
+}
+
+pub fn create(context: &Context, key: &str, metadata: &Metadata) -> Result<String, Error> {
+    // Checksums of the whole object can't be verified by S3 for multipart uploads, but parts are.
+    let mut headers = metadata_headers(metadata)
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("x-amz-meta-"))
+        .collect::<Vec<(&'static str, String)>>();
+    headers.extend(context.write_headers());
+
+    let headers = signature::sign_with(
+        "POST",
+        key,
+        "uploads",
+        "",
+        &headers,
+        signature::EMPTY_PAYLOAD,
+        context,
+    )?;
+    let response = signed(request()?.post(&url(context, key, "uploads")), headers).send()?;
+    let body = success(response, "Create Multipart Upload")?.text()?;
+
+    match xml::value(&body, "UploadId") {
+        Some(upload_id) => Ok(upload_id.to_owned()),
+        None => Err(S3Error::General {
+            reason: format!("Create Multipart Upload: UploadId is missing ({})", body),
+        }.into()),
+    }
+}
+
+pub fn upload_part(
+    context: &Context,
+    key: &str,
+    upload_id: &str,
+    part_number: usize,
+    data: &[u8],
+) -> Result<String, Error> {
+    let query = format!(
+        "partNumber={}&uploadId={}",
+        part_number,
+        signature::uri_encode(upload_id, true)
+    );
+    let payload_hash = signature::sha256_hex(data);
+
+    // Parts of objects encrypted by SSE-C are encrypted by the same key.
+    let mut headers = vec![("Content-MD5", content_md5(data))];
+    headers.extend(context.customer_key_headers());
+
+    let headers = signature::sign_with(
+        "PUT",
+        key,
+        &query,
+        "",
+        &headers,
+        &payload_hash,
+        context,
+    )?;
+    let response = signed(request()?.put(&url(context, key, &query)), headers)
+        .header("Content-Length", data.len() as u64)
+        .body(data.to_vec())
+        .send()?;
+    let response = success(response, "Upload Part")?;
+
+    match response
+        .headers()
+        .get("ETag")
+        .and_then(|etag| etag.to_str().ok())
+    {
+        Some(etag) => Ok(etag.to_owned()),
+        None => Err(S3Error::General {
+            reason: format!("Upload Part: ETag of part {} is missing", part_number),
+        }.into()),
+    }
+}
+
+pub fn complete(
+    context: &Context,
+    key: &str,
+    upload_id: &str,
+    etags: &[String],
+) -> Result<(), Error> {
+    let query = format!("uploadId={}", signature::uri_encode(upload_id, true));
+    let body = complete_body(etags);
+    let payload_hash = signature::sha256_hex(body.as_bytes());
+    let headers = signature::sign("POST", key, &query, "", &payload_hash, context)?;
+    let response = signed(request()?.post(&url(context, key, &query)), headers)
+        .body(body)
+        .send()?;
+    let body = success(response, "Complete Multipart Upload")?.text()?;
+
+    // S3 could respond 200 OK and report an error in the body.
+    if xml::value(&body, "Error").is_some() {
+        return Err(S3Error::General {
+            reason: format!("Complete Multipart Upload: {}", body),
+        }.into());
+    }
+
+    Ok(())
+}
+
+pub fn abort(context: &Context, key: &str, upload_id: &str) -> Result<(), Error> {
+    let query = format!("uploadId={}", signature::uri_encode(upload_id, true));
+    let headers = signature::sign("DELETE", key, &query, "", signature::EMPTY_PAYLOAD, context)?;
+    let response = signed(request()?.delete(&url(context, key, &query)), headers).send()?;
+
+    success(response, "Abort Multipart Upload")?;
+
+    Ok(())
+}
+
+// Parts are read sequentially and handed to the workers through a bounded channel,
+// so at most `concurrency * 2` parts are held in memory at the same time.
+fn upload_parts<R>(
+    context: &Context,
+    key: &str,
+    upload_id: &str,
+    mut reader: R,
+    part_size: u64,
+) -> Result<Vec<String>, Error>
+where
+    R: Read,
+{
+    let concurrency = (*context.concurrency()).max(1);
+    let failed = AtomicBool::new(false);
+    let (part_tx, part_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(concurrency);
+    let (result_tx, result_rx) = mpsc::channel::<(usize, Result<String, Error>)>();
+    let part_rx = Arc::new(Mutex::new(part_rx));
+
+    thread::scope(|scope| -> Result<Vec<String>, Error> {
+        for _ in 0..concurrency {
+            let part_rx = part_rx.clone();
+            let result_tx = result_tx.clone();
+            let failed = &failed;
+
+            scope.spawn(move || loop {
+                let part = part_rx.lock().unwrap().recv();
+                let (part_number, data) = match part {
+                    Ok(part) => part,
+                    Err(_) => break,
+                };
+
+                // Other parts are not worth uploading once the upload is going to be aborted.
+                if failed.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let result = retry("Upload Part", || {
+                    upload_part(context, key, upload_id, part_number, &data)
+                });
+
+                if result.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                }
+
+                if result_tx.send((part_number, result)).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // The workers own the receivers now, so that sending parts fails after all of them exited.
+        drop(part_rx);
+        drop(result_tx);
+
+        let mut part_number = 0;
+
+        while !failed.load(Ordering::SeqCst) {
+            let mut data = Vec::with_capacity(part_size as usize);
+            reader.by_ref().take(part_size).read_to_end(&mut data)?;
+
+            if data.is_empty() && part_number > 0 {
+                break;
+            }
+
+            part_number += 1;
+            debug!("Part {} ({} bytes) is queued", part_number, data.len());
+
+            if part_tx.send((part_number, data)).is_err() {
+                break;
+            }
+        }
+
+        drop(part_tx);
+
+        let mut etags: Vec<(usize, String)> = vec![];
+
+        for (part_number, result) in result_rx.iter() {
+            etags.push((part_number, result?));
+        }
+
+        if etags.len() != part_number {
+            return Err(S3Error::General {
+                reason: format!(
+                    "Upload Part: {} of {} parts are uploaded",
+                    etags.len(),
+                    part_number
+                ),
+            }.into());
+        }
+
+        etags.sort_by_key(|(part_number, _)| *part_number);
+
+        Ok(etags.into_iter().map(|(_, etag)| etag).collect())
+    })
+}
+
+fn complete_body(etags: &[String]) -> String {
+    let parts = etags
+        .iter()
+        .enumerate()
+        .map(|(i, etag)| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                etag
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+        parts
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // Upload IDs of S3 may contain characters reserved in queries.
+    const UPLOAD_ID: &str = "2~a+b/c=";
+
+    type Requests = Arc<Mutex<Vec<(String, String, usize)>>>;
+
+    // A stand-in of S3 which records requests as (method, path and query, length of the body).
+    fn stand_in() -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Requests = Arc::default();
+        let recorded = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap().to_owned();
+                let path = parts.next().unwrap().to_owned();
+                let mut len = 0;
+
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+
+                    if header.trim().is_empty() {
+                        break;
+                    }
+
+                    let (name, value) = header.split_at(header.find(':').unwrap());
+
+                    if name.eq_ignore_ascii_case("content-length") {
+                        len = value[1..].trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, etag, body) = match method.as_str() {
+                    "POST" if path.ends_with("?uploads") => (
+                        "200 OK",
+                        None,
+                        format!(
+                            "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                            UPLOAD_ID
+                        ),
+                    ),
+                    "PUT" => ("200 OK", Some(format!("\"{}\"", len)), String::new()),
+                    "POST" => ("200 OK", None, "<CompleteMultipartUploadResult/>".to_owned()),
+                    "DELETE" => ("204 No Content", None, String::new()),
+                    _ => ("405 Method Not Allowed", None, String::new()),
+                };
+
+                recorded.lock().unwrap().push((method, path, len));
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    etag.map_or(String::new(), |etag| format!("ETag: {}\r\n", etag)),
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[test]
+    fn upload_id_is_encoded_in_every_request() {
+        let (url, requests) = stand_in();
+        let mut context = Context::new("us-east-1", &url, "id", "secret", "bucket");
+        context.set_part_size(MIN_PART_SIZE);
+
+        let data = vec![0; MIN_PART_SIZE as usize + 1];
+        let len = data.len() as u64;
+
+        upload(&context, "key", data.as_slice(), len, &Metadata::default()).unwrap();
+        abort(&context, "key", UPLOAD_ID).unwrap();
+
+        let mut requests = requests.lock().unwrap().clone();
+        requests[1..3].sort();
+
+        assert_eq!(
+            requests,
+            vec![
+                ("POST".to_owned(), "/bucket/key?uploads".to_owned(), 0),
+                (
+                    "PUT".to_owned(),
+                    "/bucket/key?partNumber=1&uploadId=2~a%2Bb%2Fc%3D".to_owned(),
+                    MIN_PART_SIZE as usize
+                ),
+                (
+                    "PUT".to_owned(),
+                    "/bucket/key?partNumber=2&uploadId=2~a%2Bb%2Fc%3D".to_owned(),
+                    1
+                ),
+                (
+                    "POST".to_owned(),
+                    "/bucket/key?uploadId=2~a%2Bb%2Fc%3D".to_owned(),
+                    complete_body(&["\"5242880\"".to_owned(), "\"1\"".to_owned()]).len()
+                ),
+                (
+                    "DELETE".to_owned(),
+                    "/bucket/key?uploadId=2~a%2Bb%2Fc%3D".to_owned(),
+                    0
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn part_size_respects_minimum_and_max_parts() {
+        let mut context = Context::default();
+
+        context.set_part_size(1024);
+        assert_eq!(part_size(&context, 1024), MIN_PART_SIZE);
+
+        context.set_part_size(8 * 1024 * 1024);
+        assert_eq!(part_size(&context, 1024), 8 * 1024 * 1024);
+
+        let len = 200 * 1024 * 1024 * 1024;
+        assert!(part_size(&context, len) * MAX_PARTS >= len);
+    }
+
+    #[test]
+    fn complete_body_lists_parts_in_order() {
+        let etags = vec!["\"a\"".to_owned(), "\"b\"".to_owned()];
+
+        assert_eq!(
+            complete_body(&etags),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"b\"</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+}
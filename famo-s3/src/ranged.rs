@@ -0,0 +1,274 @@
+use super::{request, retry, signed, success, url, S3Error};
+use context::Context;
+use failure::Error;
+use signature;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+///
+/// Download an object by ranged GETs.
+/// Ranges of `context.part_size()` bytes are fetched by `context.concurrency()` threads
+/// and read in order from the returned reader.
+///
+pub fn download(context: &Context, key: &str, len: u64) -> RangedReader {
+    let context = context.clone();
+    let key = key.to_owned();
+
+    RangedReader::new(
+        ranges(len, *context.part_size()),
+        *context.concurrency(),
+        move |start, end| retry("Get Object Range", || get_range(&context, &key, start, end)),
+    )
+}
+
+///
+/// Get bytes from `start` to `end` (inclusive) of the object.
+///
+pub fn get_range(context: &Context, key: &str, start: u64, end: u64) -> Result<Vec<u8>, Error> {
+    let headers = signature::sign_with(
+        "GET",
+        key,
+        "",
+        "",
+        &context.customer_key_headers(),
+        signature::EMPTY_PAYLOAD,
+        context,
+    )?;
+    let response = signed(request()?.get(&url(context, key, "")), headers)
+        .header("Range", format!("bytes={}-{}", start, end))
+        .send()?;
+    let mut response = success(response, "Get Object Range")?;
+    let mut data = Vec::with_capacity((end - start + 1) as usize);
+
+    response.read_to_end(&mut data)?;
+
+    // A server ignoring the Range header responds the whole object.
+    if data.len() as u64 != end - start + 1 {
+        return Err(S3Error::General {
+            reason: format!(
+                "Get Object Range: {} bytes are returned for bytes={}-{}",
+                data.len(),
+                start,
+                end
+            ),
+        }.into());
+    }
+
+    Ok(data)
+}
+
+// Split `len` bytes into inclusive ranges of `size` bytes.
+fn ranges(len: u64, size: u64) -> Vec<(u64, u64)> {
+    let size = size.max(1);
+
+    (0..len.div_ceil(size))
+        .map(|i| (i * size, ((i + 1) * size).min(len) - 1))
+        .collect()
+}
+
+struct State {
+    // Index of the range to be fetched next.
+    fetching: usize,
+    // Index of the range to be read next.
+    reading: usize,
+    fetched: BTreeMap<usize, Result<Vec<u8>, Error>>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+///
+/// A reader which concatenates ranges fetched in parallel.
+/// Workers fetch at most `concurrency` ranges ahead of the reading position,
+/// so the memory usage is bounded by the number of workers and the range size.
+///
+pub struct RangedReader {
+    shared: Arc<Shared>,
+    ranges: usize,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl RangedReader {
+    pub fn new<F>(ranges: Vec<(u64, u64)>, concurrency: usize, fetch: F) -> Self
+    where
+        F: Fn(u64, u64) -> Result<Vec<u8>, Error> + Send + Sync + 'static,
+    {
+        let concurrency = concurrency.max(1);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                fetching: 0,
+                reading: 0,
+                fetched: BTreeMap::new(),
+                closed: false,
+            }),
+            cond: Condvar::new(),
+        });
+        let ranges = Arc::new(ranges);
+        let fetch = Arc::new(fetch);
+
+        for _ in 0..concurrency {
+            let shared = shared.clone();
+            let ranges = ranges.clone();
+            let fetch = fetch.clone();
+
+            thread::spawn(move || loop {
+                let index = {
+                    let mut state = shared.state.lock().unwrap();
+
+                    while !state.closed
+                        && state.fetching < ranges.len()
+                        && state.fetching >= state.reading + concurrency
+                    {
+                        state = shared.cond.wait(state).unwrap();
+                    }
+
+                    if state.closed || state.fetching >= ranges.len() {
+                        break;
+                    }
+
+                    state.fetching += 1;
+                    state.fetching - 1
+                };
+
+                let (start, end) = ranges[index];
+                let result = fetch(start, end);
+
+                let mut state = shared.state.lock().unwrap();
+                state.fetched.insert(index, result);
+                shared.cond.notify_all();
+            });
+        }
+
+        RangedReader {
+            shared,
+            ranges: ranges.len(),
+            buffer: vec![],
+            position: 0,
+        }
+    }
+
+    // Wait for the next range and make it the current buffer.
+    // Returns false when all ranges have been read.
+    fn next_range(&mut self) -> io::Result<bool> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.reading >= self.ranges {
+            return Ok(false);
+        }
+
+        loop {
+            let reading = state.reading;
+
+            if let Some(result) = state.fetched.remove(&reading) {
+                state.reading += 1;
+                self.shared.cond.notify_all();
+
+                self.buffer = result.map_err(|e| io::Error::other(e.to_string()))?;
+                self.position = 0;
+
+                return Ok(true);
+            }
+
+            state = self.shared.cond.wait(state).unwrap();
+        }
+    }
+}
+
+impl Read for RangedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.buffer.len() {
+            if !self.next_range()? {
+                return Ok(0);
+            }
+        }
+
+        let n = (&self.buffer[self.position..]).read(buf)?;
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+impl Drop for RangedReader {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ranges_cover_whole_object() {
+        assert_eq!(ranges(10, 4), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(ranges(8, 4), vec![(0, 3), (4, 7)]);
+        assert_eq!(ranges(0, 4), vec![]);
+    }
+
+    #[test]
+    fn ranged_reader_reassembles_in_order() {
+        let data = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let source = Arc::new(data.clone());
+
+        let reader = RangedReader::new(ranges(data.len() as u64, 777), 4, move |start, end| {
+            Ok(source[start as usize..=end as usize].to_vec())
+        });
+
+        let mut read = vec![];
+        reader
+            .take(data.len() as u64 + 1)
+            .read_to_end(&mut read)
+            .unwrap();
+
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn ranged_reader_retries_failed_ranges() {
+        let data = vec![7u8; 100];
+        let source = Arc::new(data.clone());
+        let failed = Arc::new(Mutex::new(HashSet::new()));
+
+        let mut reader = RangedReader::new(ranges(100, 50), 2, move |start, end| {
+            retry("test", || {
+                // The first attempt for each range fails.
+                if failed.lock().unwrap().insert(start) {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionReset, "network blip").into());
+                }
+
+                Ok(source[start as usize..=end as usize].to_vec())
+            })
+        });
+
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn ranged_reader_reports_errors() {
+        let mut reader = RangedReader::new(ranges(100, 10), 2, |start, _| {
+            if start == 50 {
+                Err(format_err!("broken range"))
+            } else {
+                Ok(vec![0; 10])
+            }
+        });
+
+        let mut read = vec![];
+        let e = reader.read_to_end(&mut read).unwrap_err();
+
+        assert!(e.to_string().contains("broken range"));
+        assert_eq!(read.len(), 50);
+    }
+}
@@ -0,0 +1,59 @@
+use context::Context;
+use failure::Error;
+use famo_storage::{Entry, Metadata, Storage};
+use std::io::Read;
+
+///
+/// Store caches as objects of an S3 bucket.
+///
+#[derive(Getters)]
+pub struct S3Storage {
+    #[get = "pub"]
+    context: Context,
+}
+
+impl S3Storage {
+    pub fn new(context: Context) -> Self {
+        Self { context }
+    }
+}
+
+impl Storage for S3Storage {
+    fn name(&self) -> &str {
+        "S3"
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        super::key_exists(&self.context, key)
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, Error> {
+        super::download_stream(&self.context, key)
+    }
+
+    fn put(&self, key: &str, reader: Box<dyn Read + Send>, len: u64) -> Result<(), Error> {
+        super::upload_stream(&self.context, key, reader, len, &Metadata::default())
+    }
+
+    fn put_with_metadata(
+        &self,
+        key: &str,
+        reader: Box<dyn Read + Send>,
+        len: u64,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        super::upload_stream(&self.context, key, reader, len, metadata)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let response = super::delete(&self.context, key)?;
+
+        super::success(response, "Delete Object")?;
+
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Entry>, Error> {
+        super::list_objects(&self.context, prefix)
+    }
+}
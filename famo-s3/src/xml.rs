@@ -0,0 +1,77 @@
+// Tiny helpers to read the flat XML responses of S3.
+// Nested elements of the same tag and attributes are not supported.
+
+///
+/// Text of the first element of the tag.
+///
+pub fn value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    values(xml, tag).into_iter().next()
+}
+
+///
+/// Texts of all elements of the tag in order.
+///
+pub fn values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let start = start + open.len();
+
+        match rest[start..].find(&close) {
+            Some(end) => {
+                values.push(&rest[start..start + end]);
+                rest = &rest[start + end + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    values
+}
+
+///
+/// Replace the predefined entities of XML.
+///
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn value_of_initiate_multipart_upload_result() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                   <InitiateMultipartUploadResult>\
+                   <Bucket>famo-cache</Bucket>\
+                   <Key>hex</Key>\
+                   <UploadId>VXBsb2FkIElE</UploadId>\
+                   </InitiateMultipartUploadResult>";
+
+        assert_eq!(value(xml, "UploadId"), Some("VXBsb2FkIElE"));
+        assert_eq!(value(xml, "Error"), None);
+    }
+
+    #[test]
+    fn values_of_list_bucket_result() {
+        let xml = "<ListBucketResult>\
+                   <Contents><Key>a</Key></Contents>\
+                   <Contents><Key>b&amp;c</Key></Contents>\
+                   </ListBucketResult>";
+
+        let contents = values(xml, "Contents");
+        assert_eq!(contents.len(), 2);
+        assert_eq!(
+            value(contents[1], "Key").map(unescape),
+            Some("b&c".to_owned())
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use failure::Error;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use StorageError;
+
+///
+/// A reader which hashes everything read through it by SHA-256.
+///
+pub struct Sha256Reader<R> {
+    inner: R,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R: Read> Sha256Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+        }
+    }
+
+    /// Bytes read so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hex SHA-256 of the bytes read so far.
+    pub fn hex(&self) -> String {
+        to_hex(&self.hasher.clone().result())
+    }
+}
+
+impl<R: Read> Read for Sha256Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.input(&buf[..n]);
+        self.len += n as u64;
+
+        Ok(n)
+    }
+}
+
+///
+/// Hex SHA-256 of everything read from the reader.
+///
+pub fn sha256_hex<R: Read>(reader: R) -> Result<String, Error> {
+    let mut reader = Sha256Reader::new(reader);
+    io::copy(&mut reader, &mut io::sink())?;
+
+    Ok(reader.hex())
+}
+
+///
+/// Copy the object into the writer and check its length and SHA-256.
+/// The writer must not be used when it fails, since it has a corrupted object.
+///
+pub fn copy_verified<R, W>(key: &str, reader: R, writer: &mut W, len: u64, sha256: &str) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut reader = Sha256Reader::new(reader);
+    io::copy(&mut reader, writer)?;
+
+    let reason = if reader.len() != len {
+        format!("{} bytes are read while the object has {} bytes", reader.len(), len)
+    } else if !reader.hex().eq_ignore_ascii_case(sha256) {
+        format!("SHA-256 is {} while {} is expected", reader.hex(), sha256)
+    } else {
+        return Ok(());
+    };
+
+    Err(StorageError::Corrupted {
+        key: key.to_owned(),
+        reason,
+    }.into())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn sha256_of_reader() {
+        assert_eq!(sha256_hex(&b"hello"[..]).unwrap(), HELLO);
+    }
+
+    #[test]
+    fn copy_verified_rejects_corrupted_objects() {
+        let mut copied = vec![];
+        copy_verified("key", &b"hello"[..], &mut copied, 5, HELLO).unwrap();
+        assert_eq!(copied, b"hello");
+
+        let truncated = copy_verified("key", &b"hell"[..], &mut vec![], 5, HELLO);
+        assert!(truncated.unwrap_err().to_string().contains("4 bytes are read"));
+
+        let corrupted = copy_verified("key", &b"jello"[..], &mut vec![], 5, HELLO);
+
+        match corrupted.unwrap_err().downcast::<StorageError>() {
+            Ok(StorageError::Corrupted { key, .. }) => assert_eq!(key, "key"),
+            e => panic!("Unexpected result {:?}", e),
+        }
+    }
+}
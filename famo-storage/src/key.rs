@@ -0,0 +1,119 @@
+use failure::Error;
+use StorageError;
+
+///
+/// Key of a cache in storages. (`{prefix}/{project}/{lang}/{hex}{suffix}`)
+/// Segments which aren't given are omitted, and the prefix may have `/` in itself.
+///
+/// ```rust
+/// use famo_storage::key::ObjectKey;
+///
+/// let mut key = ObjectKey::new("v1-0123");
+/// key.set_prefix(Some("ci/caches".to_owned()));
+/// key.set_lang(Some("rust".to_owned()));
+///
+/// assert_eq!(key.key().unwrap(), "ci/caches/rust/v1-0123");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Getters, Setters)]
+pub struct ObjectKey {
+    #[get = "pub"]
+    #[set = "pub"]
+    prefix: Option<String>,
+    #[get = "pub"]
+    #[set = "pub"]
+    project: Option<String>,
+    #[get = "pub"]
+    #[set = "pub"]
+    lang: Option<String>,
+    #[get = "pub"]
+    hex: String,
+    #[get = "pub"]
+    #[set = "pub"]
+    suffix: Option<String>,
+}
+
+impl ObjectKey {
+    pub fn new(hex: &str) -> Self {
+        Self {
+            hex: hex.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Segments of the key separated by `/`.
+    /// Segments can't be empty, `.` or `..`, so that every backend stores the key as it is.
+    ///
+    pub fn segments(&self) -> Result<Vec<String>, Error> {
+        let mut segments: Vec<String> = vec![];
+
+        if let Some(ref prefix) = self.prefix {
+            segments.extend(
+                prefix
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .map(|segment| segment.to_owned()),
+            );
+        }
+
+        segments.extend(self.project.iter().cloned());
+        segments.extend(self.lang.iter().cloned());
+        segments.push(format!(
+            "{}{}",
+            self.hex,
+            self.suffix.as_ref().map_or("", |suffix| suffix.as_str())
+        ));
+
+        let invalid = |segment: &String| {
+            segment.is_empty() || segment == "." || segment == ".." || segment.contains('/')
+        };
+
+        if segments.iter().any(invalid) {
+            return Err(StorageError::InvalidKey {
+                key: segments.join("/"),
+            }.into());
+        }
+
+        Ok(segments)
+    }
+
+    pub fn key(&self) -> Result<String, Error> {
+        Ok(self.segments()?.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn segments_are_omitted_unless_given() {
+        let mut key = ObjectKey::new("v1-0123");
+        assert_eq!(key.key().unwrap(), "v1-0123");
+
+        key.set_prefix(Some("/team/app/".to_owned()));
+        key.set_project(Some("my project".to_owned()));
+        key.set_lang(Some("rust".to_owned()));
+        key.set_suffix(Some(".tar.gz".to_owned()));
+
+        assert_eq!(
+            key.segments().unwrap(),
+            vec!["team", "app", "my project", "rust", "v1-0123.tar.gz"]
+        );
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        let mut key = ObjectKey::new("v1-0123");
+
+        key.set_project(Some("..".to_owned()));
+        assert!(key.key().is_err());
+
+        key.set_project(Some("a/b".to_owned()));
+        assert!(key.key().is_err());
+
+        key.set_project(None);
+        key.set_suffix(Some("/x".to_owned()));
+        assert!(key.key().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate getset;
+extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
+
+pub mod checksum;
+pub mod key;
+pub mod local;
+
+use failure::Error;
+use std::io::Read;
+use std::time::SystemTime;
+
+#[derive(Debug, Fail)]
+pub enum StorageError {
+    #[fail(display = "Invalid key '{}'", key)]
+    InvalidKey { key: String },
+    #[fail(display = "The cache '{}' is corrupted ({})", key, reason)]
+    Corrupted { key: String, reason: String },
+}
+
+///
+/// An object stored in a storage.
+///
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Entry {
+    #[get = "pub"]
+    key: String,
+    #[get = "pub"]
+    size: u64,
+    #[get = "pub"]
+    last_modified: SystemTime,
+}
+
+impl Entry {
+    pub fn new(key: &str, size: u64, last_modified: SystemTime) -> Self {
+        Self {
+            key: key.to_owned(),
+            size,
+            last_modified,
+        }
+    }
+}
+
+///
+/// Metadata stored with objects by backends which can keep it.
+///
+#[derive(Debug, Clone, Default, PartialEq, Getters, Setters)]
+pub struct Metadata {
+    /// Hex SHA-256 of the object, which `get` verifies before it's read.
+    #[get = "pub"]
+    #[set = "pub"]
+    sha256: Option<String>,
+    /// ID of the key which the object is encrypted by.
+    #[get = "pub"]
+    #[set = "pub"]
+    key_id: Option<String>,
+}
+
+///
+/// A backend to store caches.
+/// Keys are `/` separated paths such as `{key}/{cache hex}`. (See `key::ObjectKey`)
+///
+pub trait Storage {
+    /// Human readable name of the backend for logging.
+    fn name(&self) -> &str;
+
+    fn exists(&self, key: &str) -> Result<bool, Error>;
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, Error>;
+
+    /// Store `len` bytes read from the reader.
+    fn put(&self, key: &str, reader: Box<dyn Read + Send>, len: u64) -> Result<(), Error>;
+
+    ///
+    /// Store `len` bytes with the metadata. Backends which can't keep metadata store them as `put` does.
+    ///
+    fn put_with_metadata(
+        &self,
+        key: &str,
+        reader: Box<dyn Read + Send>,
+        len: u64,
+        _metadata: &Metadata,
+    ) -> Result<(), Error> {
+        self.put(key, reader, len)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error>;
+
+    /// Objects whose keys start with the prefix.
+    fn list(&self, prefix: &str) -> Result<Vec<Entry>, Error>;
+
+    /// The most recently written object whose key starts with the prefix.
+    fn latest(&self, prefix: &str) -> Result<Option<Entry>, Error> {
+        let entries = self.list(prefix)?;
+
+        // Keys break ties so that the same object is chosen on every job.
+        Ok(entries
+            .into_iter()
+            .max_by(|a, b| (a.last_modified(), a.key()).cmp(&(b.last_modified(), b.key()))))
+    }
+}
@@ -0,0 +1,287 @@
+use failure::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use {Entry, Storage, StorageError};
+
+// Directory of files being written by `put`, which are not objects yet.
+const PARTIAL_DIR: &str = ".famo-partial";
+
+///
+/// Store caches as files under a directory.
+///
+/// ```rust
+/// use famo_storage::local::LocalStorage;
+/// use famo_storage::Storage;
+///
+/// let storage = LocalStorage::new(&"/mnt/famo-cache");
+/// println!("Caches are stored by {}", storage.name());
+/// ```
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(dir: &dyn AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // Keys must stay inside of the directory, and out of the directory of partial files.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        let relative = Path::new(key);
+        let valid = !key.is_empty()
+            && !relative.starts_with(PARTIAL_DIR)
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+        if !valid {
+            return Err(StorageError::InvalidKey {
+                key: key.to_owned(),
+            }.into());
+        }
+
+        Ok(self.dir.join(relative))
+    }
+
+    fn list_dir(&self, dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> Result<(), Error> {
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+
+            if path == self.dir.join(PARTIAL_DIR) {
+                continue;
+            }
+
+            if path.is_dir() {
+                self.list_dir(&path, prefix, entries)?;
+                continue;
+            }
+
+            let key = path
+                .strip_prefix(&self.dir)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if !key.starts_with(prefix) {
+                continue;
+            }
+
+            let metadata = path.metadata()?;
+            entries.push(Entry::new(&key, metadata.len(), metadata.modified()?));
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for LocalStorage {
+    fn name(&self) -> &str {
+        "local directory"
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.path(key)?.is_file())
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, Error> {
+        Ok(Box::new(File::open(self.path(key)?)?))
+    }
+
+    // The object is written into the directory of partial files and renamed at last,
+    // so that readers never see a partially written object.
+    fn put(&self, key: &str, mut reader: Box<dyn Read + Send>, _len: u64) -> Result<(), Error> {
+        static PARTIALS: AtomicUsize = AtomicUsize::new(0);
+
+        let path = self.path(key)?;
+        let partials = self.dir.join(PARTIAL_DIR);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::create_dir_all(&partials)?;
+
+        // Unique among writers in this process and the others.
+        let partial = partials.join(format!(
+            "{}-{}",
+            process::id(),
+            PARTIALS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let result = File::create(&partial)
+            .and_then(|mut file| io::copy(&mut reader, &mut file))
+            .and_then(|_| fs::rename(&partial, &path));
+
+        if let Err(e) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        fs::remove_file(self.path(key)?)?;
+
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Entry>, Error> {
+        let mut entries = vec![];
+
+        if self.dir.is_dir() {
+            self.list_dir(&self.dir, prefix, &mut entries)?;
+        }
+
+        entries.sort_by(|a, b| a.key().cmp(b.key()));
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile;
+
+    fn put(storage: &LocalStorage, key: &str, data: &'static [u8]) {
+        storage.put(key, Box::new(data), data.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn put_get_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path());
+
+        assert!(!storage.exists("famo/hex").unwrap());
+
+        put(&storage, "famo/hex", b"cache");
+        assert!(storage.exists("famo/hex").unwrap());
+
+        let mut data = vec![];
+        storage
+            .get("famo/hex")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"cache");
+
+        storage.delete("famo/hex").unwrap();
+        assert!(!storage.exists("famo/hex").unwrap());
+    }
+
+    #[test]
+    fn list_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path());
+
+        put(&storage, "a/master/0", b"0");
+        put(&storage, "a/master/1", b"11");
+        put(&storage, "a/feature/2", b"222");
+        put(&storage, "b/master/3", b"3333");
+
+        let keys = storage
+            .list("a/master/")
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.key().to_owned(), *entry.size()))
+            .collect::<Vec<(String, u64)>>();
+
+        assert_eq!(
+            keys,
+            vec![("a/master/0".to_owned(), 1), ("a/master/1".to_owned(), 2)]
+        );
+        assert_eq!(storage.list("").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn latest_of_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path());
+
+        assert!(storage.latest("a/").unwrap().is_none());
+
+        for key in &["a/master/1", "a/master/0", "a/feature/2"] {
+            put(&storage, key, b"cache");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let latest = |prefix| storage.latest(prefix).unwrap().map(|entry| entry.key().to_owned());
+
+        assert_eq!(latest("a/master/"), Some("a/master/0".to_owned()));
+        assert_eq!(latest("a/"), Some("a/feature/2".to_owned()));
+        assert_eq!(latest("b/"), None);
+    }
+
+    #[test]
+    fn list_missing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path().join("missing"));
+
+        assert!(storage.list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_escaping_directory_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path());
+
+        assert!(storage.exists("../hex").is_err());
+        assert!(storage.exists("/etc/passwd").is_err());
+        assert!(storage.exists("").is_err());
+        assert!(storage.exists(".famo-partial/hex").is_err());
+    }
+
+    // A reader which stores another object while it's read, as a concurrent writer does.
+    struct Interleaved {
+        dir: PathBuf,
+        data: &'static [u8],
+    }
+
+    impl Read for Interleaved {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.data.is_empty() {
+                put(&LocalStorage::new(&self.dir), "x.b", b"b");
+            }
+
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn partial_files_are_not_shared_by_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(&dir.path());
+        let reader = Interleaved {
+            dir: dir.path().to_path_buf(),
+            data: b"a",
+        };
+
+        storage.put("x.a", Box::new(reader), 1).unwrap();
+        put(&storage, "y.famo-partial", b"y");
+
+        let read = |key| {
+            let mut data = vec![];
+            storage.get(key).unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+
+        assert_eq!((read("x.a"), read("x.b")), (b"a".to_vec(), b"b".to_vec()));
+
+        // Keys which look like partial files are objects as well.
+        let keys = storage
+            .list("")
+            .unwrap()
+            .iter()
+            .map(|entry| entry.key().to_owned())
+            .collect::<Vec<String>>();
+        assert_eq!(keys, vec!["x.a", "x.b", "y.famo-partial"]);
+    }
+}
@@ -0,0 +1,217 @@
+use clap::ArgMatches;
+use cli;
+use error::FamoError;
+use failure::Error;
+use phase;
+use state::{State, STATE_DIR};
+use std::env;
+use std::fs::File;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Set for the background process which uploads the archive.
+pub const UPLOADER: &str = "FAMO_UPLOADER";
+
+const PID: &str = "upload.pid";
+const KEY: &str = "upload.key";
+const ARCHIVE: &str = "upload.archive";
+const PROGRESS: &str = "upload.progress";
+const RESULT: &str = "upload.result";
+const LOG: &str = "upload.log";
+
+const SUCCESS: &str = "success";
+
+///
+/// Upload the archive by a detached background process.
+/// The process is famo itself executed with the same arguments, which finds the key and the archive
+/// in the state and records its progress and result there for `famo wait`.
+///
+pub fn spawn(key: &str, archive: &str) -> Result<(), Error> {
+    let state = State::new(&".");
+
+    for name in &[PID, PROGRESS, RESULT] {
+        state.remove(name)?;
+    }
+
+    state.write(KEY, key)?;
+    state.write(ARCHIVE, archive)?;
+    state.write(PROGRESS, "starting")?;
+
+    let mut command = Command::new(env::current_exe()?);
+
+    command
+        .args(env::args_os().skip(1))
+        .env(UPLOADER, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(File::create(state.path(LOG))?);
+
+    detach(&mut command);
+
+    let child = command.spawn()?;
+    state.write(PID, &child.id().to_string())?;
+
+    info!(
+        "Uploading in background (pid {}). `famo wait` waits for it. (Logs are in {}/{})",
+        child.id(),
+        STATE_DIR,
+        LOG
+    );
+
+    Ok(())
+}
+
+// The process keeps running after famo exits and isn't killed together with the group of famo.
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn detach(_command: &mut Command) {}
+
+///
+/// Forget the upload of the previous job, so that `famo wait` doesn't report it.
+///
+pub fn clear() -> Result<(), Error> {
+    let state = State::new(&".");
+
+    for name in &[PID, KEY, ARCHIVE, PROGRESS, RESULT, LOG] {
+        state.remove(name)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Run as the background process.
+///
+pub fn upload(matches: &ArgMatches) -> Result<(), Error> {
+    let state = State::new(&".");
+    let result = upload_inner(matches, &state);
+
+    match result {
+        Ok(()) => {
+            state.write("cached", "true")?;
+            state.write(RESULT, SUCCESS)?;
+        }
+        Err(ref e) => state.write(RESULT, &format!("failure: {}", e))?,
+    }
+
+    result
+}
+
+fn upload_inner(matches: &ArgMatches, state: &State) -> Result<(), Error> {
+    let (key, archive) = match (state.read(KEY)?, state.read(ARCHIVE)?) {
+        (Some(key), Some(archive)) => (key, archive),
+        _ => return Err(format_err!("Nothing to upload in {}", STATE_DIR)),
+    };
+
+    let storage = cli::storage(matches)?;
+    let keys = cli::encryption_keys(matches)?;
+    let archive_options = cli::archive_options(matches)?;
+
+    state.write(PROGRESS, "archiving")?;
+
+    let progress = State::new(&".");
+
+    phase::upload_archive_with_progress(
+        storage.as_ref(),
+        &key,
+        &archive,
+        &archive_options,
+        &keys,
+        Box::new(move |read, len| {
+            if let Err(e) = progress.write(PROGRESS, &format!("uploading {}/{} bytes", read, len)) {
+                warn!("Failed to record the progress: {}", e);
+            }
+        }),
+    )
+}
+
+///
+/// Wait for the background process and fail when the upload has failed.
+///
+pub fn wait() -> Result<(), Error> {
+    let state = State::new(&".");
+
+    if state.read(KEY)?.is_none() {
+        info!("Nothing is uploaded in background.");
+        return Ok(());
+    }
+
+    let mut last_progress = None;
+
+    loop {
+        if let Some(result) = state.read(RESULT)? {
+            if result == SUCCESS {
+                info!("The upload in background has succeeded.");
+                return Ok(());
+            }
+
+            return Err(FamoError::UploadFailed { reason: result }.into());
+        }
+
+        let progress = state.read(PROGRESS)?;
+
+        if progress != last_progress {
+            if let Some(ref progress) = progress {
+                info!("Uploading in background: {}", progress);
+            }
+
+            last_progress = progress;
+        }
+
+        if let Some(pid) = state.read(PID)? {
+            // The result could be recorded right before the process exits.
+            if !alive(&pid)? && state.read(RESULT)?.is_none() {
+                return Err(FamoError::UploadFailed {
+                    reason: format!(
+                        "The process {} has exited without the result. (See {}/{})",
+                        pid, STATE_DIR, LOG
+                    ),
+                }.into());
+            }
+        }
+
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[cfg(unix)]
+fn alive(pid: &str) -> Result<bool, Error> {
+    let alive = Command::new("kill")
+        .arg("-0")
+        .arg(pid)
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true);
+
+    Ok(alive)
+}
+
+// tasklist prints the process as a CSV row when it exists, and only a message otherwise.
+#[cfg(windows)]
+fn alive(pid: &str) -> Result<bool, Error> {
+    let filter = format!("PID eq {}", pid);
+    let alive = Command::new("tasklist")
+        .args(&["/FI", filter.as_str(), "/FO", "CSV", "/NH"])
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+        .unwrap_or(true);
+
+    Ok(alive)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn alive(pid: &str) -> Result<bool, Error> {
+    Err(format_err!(
+        "Can't tell whether the process {} uploading in background is alive on this platform",
+        pid
+    ))
+}
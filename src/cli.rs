@@ -1,25 +1,103 @@
-use clap::{App, Arg, ArgMatches};
-use error::FamoError;
+use clap::{App, Arg, ArgMatches, ErrorKind, SubCommand};
+use error::{self, FamoError};
 use failure::Error;
+use famo_lib::archive::Options as ArchiveOptions;
+use famo_lib::crypt::{self, Key};
+use famo_lib::hash::Options as HashOptions;
+use famo_lib::http::context::Context as HttpContext;
+use famo_lib::http::HttpStorage;
 use famo_lib::lang::Lang;
+use famo_lib::redis::context::Context as RedisContext;
+use famo_lib::redis::RedisStorage;
+use famo_lib::s3::context::{self as s3_context, Context as S3Context};
+use famo_lib::s3::storage::S3Storage;
+use famo_lib::storage::key::ObjectKey;
+use famo_lib::storage::local::LocalStorage;
+use famo_lib::storage::Storage;
+use std::process;
 
 pub fn matches<'a>() -> ArgMatches<'a> {
     App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about("An Engine for Caching Builds on CI/CD")
+        .arg(arg_backend())
+        .arg(arg_local_dir())
+        .arg(arg_redis_url())
+        .arg(arg_redis_ttl())
+        .arg(arg_redis_chunk_size())
+        .arg(arg_http_url())
+        .arg(arg_http_user())
+        .arg(arg_http_password())
+        .arg(arg_http_token())
         .arg(arg_access_key_id())
         .arg(arg_secret_access_key())
+        .arg(arg_session_token())
         .arg(arg_bucket())
         .arg(arg_endpoint())
         .arg(arg_region())
         .arg(arg_key())
+        .arg(arg_project())
+        .arg(arg_key_suffix())
+        .arg(arg_restore_key())
+        .arg(arg_key_env())
+        .arg(arg_key_probe())
+        .arg(arg_addressing_style())
+        .arg(arg_signature_version())
+        .arg(arg_part_size())
+        .arg(arg_concurrency())
+        .arg(arg_server_side_encryption())
+        .arg(arg_sse_kms_key_id())
+        .arg(arg_sse_customer_key())
+        .arg(arg_storage_class())
+        .arg(arg_tagging())
+        .arg(arg_acl())
         .arg(arg_archive())
         .arg(arg_command())
         .arg(arg_verbose())
         .arg(arg_async())
+        .arg(arg_cache_on_failure())
+        .arg(arg_encryption_key())
+        .arg(arg_encryption_key_file())
+        .arg(arg_compression())
+        .arg(arg_compression_level())
+        .arg(arg_compression_threads())
+        .arg(arg_preserve())
+        .arg(arg_normalize_mtime())
+        .arg(arg_max_unpacked_size())
+        .arg(arg_max_entries())
+        .arg(arg_symlinks())
+        .arg(arg_watch_exclude())
+        .arg(arg_watch_gitignore())
+        .arg(arg_watch_missing())
         .arg(arg_watch())
-        .get_matches()
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Download and unpack the cache if it exists")
+                .arg(arg_watch()),
+        )
+        .subcommand(
+            SubCommand::with_name("save")
+                .about("Archive and upload the cache unless `restore` has found it")
+                .arg(arg_watch()),
+        )
+        .subcommand(
+            SubCommand::with_name("wait")
+                .about("Wait for the upload in background and exit with its result"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Execute the build command and record its exit status for `save`")
+                .arg(arg_run()),
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                process::exit(error::EXIT_CONFIG)
+            }
+        })
 }
 
 pub fn archive<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Result<&'a str, Error> {
@@ -52,6 +130,157 @@ pub fn watches<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Result<Vec<&
     }.into())
 }
 
+///
+/// Key of the cache in the storage. (`{key}/{project}/{lang}/{cache hex}{suffix}`)
+///
+pub fn object_key(matches: &ArgMatches, lang: &Option<Lang>, hex: &str) -> Result<String, Error> {
+    let mut key = ObjectKey::new(hex);
+
+    key.set_prefix(matches.value_of("key").map(|prefix| prefix.to_owned()));
+    key.set_project(matches.value_of("project").map(|project| project.to_owned()));
+    key.set_lang(lang.as_ref().map(|lang| lang.name().to_string()));
+    key.set_suffix(matches.value_of("key_suffix").map(|suffix| suffix.to_owned()));
+
+    key.key().map_err(|e| {
+        FamoError::MissedOption {
+            description: format!("{}. (--key, --project and --key_suffix can't make empty, '.' or '..' segments)", e),
+        }.into()
+    })
+}
+
+///
+/// Prefixes of keys tried in the order when the cache of the key doesn't exist.
+///
+pub fn restore_keys<'a>(matches: &'a ArgMatches) -> Vec<&'a str> {
+    matches
+        .values_of("restore_key")
+        .map(|keys| keys.collect())
+        .unwrap_or_default()
+}
+
+///
+/// Environment variables included in the key. Those of the detected language are used by default.
+///
+pub fn key_envs<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Vec<&'a str> {
+    if let Some(envs) = matches.values_of("key_env") {
+        return envs.collect();
+    }
+
+    lang.as_ref().map(|lang| lang.envs().clone()).unwrap_or_default()
+}
+
+///
+/// Commands whose outputs are included in the key. Those of the detected language are used by default.
+///
+pub fn key_probes<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Vec<&'a str> {
+    if let Some(probes) = matches.values_of("key_probe") {
+        return probes.collect();
+    }
+
+    lang.as_ref().map(|lang| lang.probes().clone()).unwrap_or_default()
+}
+
+///
+/// Keys encrypting caches. The first key encrypts caches to be uploaded and all of them decrypt restored ones,
+/// so that keys can be rotated by prepending new keys.
+///
+pub fn encryption_keys(matches: &ArgMatches) -> Result<Vec<Key>, Error> {
+    let invalid = |e: Error| -> Error {
+        FamoError::MissedOption {
+            description: format!("{}. (--encryption_key and --encryption_key_file take 32 bytes in base64)", e),
+        }.into()
+    };
+
+    let mut keys = matches
+        .values_of("encryption_key")
+        .map(|keys| keys.map(Key::parse).collect::<Result<Vec<Key>, Error>>())
+        .unwrap_or_else(|| Ok(vec![]))
+        .map_err(invalid)?;
+
+    if let Some(path) = matches.value_of("encryption_key_file") {
+        keys.extend(crypt::keys_of_file(&path).map_err(invalid)?);
+    }
+
+    Ok(keys)
+}
+
+///
+/// The codec compressing caches and the attributes of files preserved in them.
+/// Caches are restored whichever codec compressed them.
+///
+pub fn archive_options(matches: &ArgMatches) -> Result<ArchiveOptions, Error> {
+    let invalid = |option: &str, description: String| -> Error {
+        FamoError::MissedOption {
+            description: format!("{}. (--{})", description, option),
+        }.into()
+    };
+    let mut options = ArchiveOptions::default();
+
+    if let Some(codec) = matches.value_of("compression") {
+        options.codec = codec.parse().unwrap();
+    }
+
+    if let Some(level) = matches.value_of("compression_level") {
+        let level = level
+            .parse()
+            .map_err(|_| invalid("compression_level", format!("'{}' is too large", level)))?;
+        options.level = Some(level);
+    }
+
+    if let Some(threads) = matches.value_of("compression_threads") {
+        options.threads = threads
+            .parse()
+            .map_err(|_| invalid("compression_threads", format!("'{}' is too large", threads)))?;
+    }
+
+    if let Some(attributes) = matches.values_of("preserve") {
+        let attributes: Vec<&str> = attributes.collect();
+
+        options.attributes.mtime = attributes.contains(&"mtime");
+        options.attributes.permissions = attributes.contains(&"permissions");
+        options.attributes.ownership = attributes.contains(&"ownership");
+        options.attributes.xattrs = attributes.contains(&"xattrs");
+    }
+
+    if let Some(mtime) = matches.value_of("normalize_mtime") {
+        options.attributes.normalized_mtime = Some(mtime.parse().unwrap());
+    }
+
+    if let Some(size) = mebibytes(matches, "max_unpacked_size")? {
+        options.limits.max_size = size;
+    }
+
+    if let Some(entries) = matches.value_of("max_entries") {
+        options.limits.max_entries = entries.parse().unwrap();
+    }
+
+    options
+        .validate()
+        .map_err(|e| invalid("compression_level", e.to_string()))?;
+
+    Ok(options)
+}
+
+pub fn hash_options(matches: &ArgMatches) -> HashOptions {
+    let mut options = HashOptions::default();
+
+    if let Some(symlinks) = matches.value_of("symlinks") {
+        options.symlinks = symlinks.parse().unwrap();
+    }
+
+    if let Some(excludes) = matches.values_of("watch_exclude") {
+        options.excludes = excludes.map(|exclude| exclude.to_owned()).collect();
+    }
+
+    if let Some(missing) = matches.value_of("watch_missing") {
+        options.missing = missing.parse().unwrap();
+    }
+
+    options.gitignore = matches.is_present("watch_gitignore");
+
+    options
+}
+
 pub fn command<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Result<&'a str, Error> {
     if let Some(command) = matches.value_of("command") {
         return Ok(command);
@@ -67,14 +296,204 @@ pub fn command<'a>(matches: &'a ArgMatches, lang: &Option<Lang>) -> Result<&'a s
     }.into())
 }
 
+pub fn storage(matches: &ArgMatches) -> Result<Box<dyn Storage>, Error> {
+    match matches.value_of("backend") {
+        Some("local") => {
+            let dir = match matches.value_of("local_dir") {
+                Some(dir) => dir,
+                None => {
+                    return Err(FamoError::MissedOption {
+                        description: "Directory of the local backend is not specified. (--local_dir=[directory])\n".to_owned()
+                            + "You can see help messages by 'famo -h'",
+                    }.into())
+                }
+            };
+
+            Ok(Box::new(LocalStorage::new(&dir)))
+        }
+        Some("http") => {
+            if !matches.is_present("http_url") {
+                return Err(FamoError::MissedOption {
+                    description:
+                        "Base URL of the HTTP backend is not specified. (--http_url=[url])\n"
+                            .to_owned()
+                            + "You can see help messages by 'famo -h'",
+                }.into());
+            }
+
+            Ok(Box::new(HttpStorage::new(HttpContext::from_matches(
+                matches,
+            ))))
+        }
+        Some("redis") => {
+            if !matches.is_present("redis_url") {
+                return Err(FamoError::MissedOption {
+                    description: "URL of the Redis backend is not specified. (--redis_url=redis://[host]:[port]/[db])\n".to_owned()
+                        + "You can see help messages by 'famo -h'",
+                }.into());
+            }
+
+            let context = RedisContext::from_matches(matches).map_err(|e| FamoError::MissedOption {
+                description: e.to_string(),
+            })?;
+
+            match RedisStorage::new(context) {
+                Ok(storage) => Ok(Box::new(storage)),
+                Err(e) => Err(FamoError::Backend {
+                    backend: "redis".to_owned(),
+                    description: e.to_string(),
+                }.into()),
+            }
+        }
+        _ => {
+            // Credentials are also found by the standard chain of AWS providers.
+            let options = ["bucket", "endpoint", "region"];
+
+            if let Some(option) = options.iter().find(|option| !matches.is_present(option)) {
+                return Err(FamoError::MissedOption {
+                    description: format!("S3 backend requires --{}.\n", option)
+                        + "You can see help messages by 'famo -h'",
+                }.into());
+            }
+
+            // Sizes are checked here to be config errors, though the context reads them by itself.
+            mebibytes(matches, "part_size")?;
+
+            match S3Context::from_matches(matches) {
+                Ok(context) => Ok(Box::new(S3Storage::new(context))),
+                Err(e) => Err(FamoError::Backend {
+                    backend: "s3".to_owned(),
+                    description: e.to_string(),
+                }.into()),
+            }
+        }
+    }
+}
+
+///
+/// The build command given after `--` of `famo run`.
+/// Arguments are quoted to be executed by `sh -c` as they are.
+///
+pub fn run(matches: &ArgMatches) -> String {
+    matches
+        .values_of("run")
+        .map(|args| args.map(quote).collect::<Vec<String>>().join(" "))
+        .unwrap_or_default()
+}
+
+fn quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+
+    if safe {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn arg_backend<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("backend")
+        .help("Backend for storing caches")
+        .takes_value(true)
+        .long("backend")
+        .env("FAMO_BACKEND")
+        .possible_values(&["s3", "local", "redis", "http"])
+        .default_value("s3")
+        .global(true)
+}
+
+fn arg_local_dir<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("local_dir")
+        .help("A directory for storing caches with the local backend")
+        .takes_value(true)
+        .long("local_dir")
+        .env("FAMO_LOCAL_DIR")
+        .global(true)
+}
+
+fn arg_redis_url<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("redis_url")
+        .help("URL of Redis for storing caches with the redis backend (redis://[host]:[port]/[db])")
+        .takes_value(true)
+        .long("redis_url")
+        .env("FAMO_REDIS_URL")
+        .hide_env_values(true)
+        .global(true)
+}
+
+fn arg_redis_ttl<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("redis_ttl")
+        .help("Seconds until caches on Redis expire. (0 never expires, default is 7 days)")
+        .takes_value(true)
+        .long("redis_ttl")
+        .env("FAMO_REDIS_TTL")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_redis_chunk_size<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("redis_chunk_size")
+        .help("Size of each chunk in MiB. Caches are split into chunks to be stored on Redis.")
+        .takes_value(true)
+        .long("redis_chunk_size")
+        .env("FAMO_REDIS_CHUNK_SIZE")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_http_url<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("http_url")
+        .help(
+            "Base URL for storing caches with the http backend (e.g. Artifactory, Nexus or WebDAV)",
+        )
+        .takes_value(true)
+        .long("http_url")
+        .env("FAMO_HTTP_URL")
+        .global(true)
+}
+
+fn arg_http_user<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("http_user")
+        .help("User for basic authentication of the http backend")
+        .takes_value(true)
+        .long("http_user")
+        .env("FAMO_HTTP_USER")
+        .global(true)
+}
+
+fn arg_http_password<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("http_password")
+        .help("Password for basic authentication of the http backend")
+        .takes_value(true)
+        .long("http_password")
+        .env("FAMO_HTTP_PASSWORD")
+        .hide_env_values(true)
+        .global(true)
+}
+
+fn arg_http_token<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("http_token")
+        .help("Token for bearer authentication of the http backend")
+        .takes_value(true)
+        .long("http_token")
+        .env("FAMO_HTTP_TOKEN")
+        .hide_env_values(true)
+        .conflicts_with("http_user")
+        .global(true)
+}
+
 fn arg_access_key_id<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("access_key_id")
         .help("Access Key ID for S3 uploads/downloads")
         .takes_value(true)
         .long("access_key_id")
         .env("FAMO_ACCESS_KEY_ID")
-        .required(true)
         .hide_env_values(true)
+        .requires("secret_access_key")
+        .global(true)
 }
 
 fn arg_secret_access_key<'a, 'b>() -> Arg<'a, 'b> {
@@ -83,8 +502,20 @@ fn arg_secret_access_key<'a, 'b>() -> Arg<'a, 'b> {
         .takes_value(true)
         .long("secret_access_key")
         .env("FAMO_SECRET_ACCESS_KEY")
-        .required(true)
         .hide_env_values(true)
+        .requires("access_key_id")
+        .global(true)
+}
+
+fn arg_session_token<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("session_token")
+        .help("Session token of temporary credentials for S3 uploads/downloads")
+        .takes_value(true)
+        .long("session_token")
+        .env("FAMO_SESSION_TOKEN")
+        .hide_env_values(true)
+        .requires("access_key_id")
+        .global(true)
 }
 
 fn arg_bucket<'a, 'b>() -> Arg<'a, 'b> {
@@ -94,17 +525,17 @@ fn arg_bucket<'a, 'b>() -> Arg<'a, 'b> {
         .long("bucket")
         .short("b")
         .env("FAMO_BUCKET")
-        .required(true)
+        .global(true)
 }
 
 fn arg_endpoint<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("endpoint")
-        .help("An endpoint for uploading/downloading caches")
+        .help("An endpoint for uploading/downloading caches, which may have a scheme and a port (http://localhost:9000)")
         .takes_value(true)
         .long("endpoint")
         .short("e")
         .env("FAMO_ENDPOINT")
-        .required(true)
+        .global(true)
 }
 
 fn arg_region<'a, 'b>() -> Arg<'a, 'b> {
@@ -114,17 +545,290 @@ fn arg_region<'a, 'b>() -> Arg<'a, 'b> {
         .long("region")
         .short("r")
         .env("FAMO_REGION")
-        .required(true)
+        .global(true)
 }
 
 fn arg_key<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("key")
-        .help("Optional key of the object on S3. ({bucket}/{key}/{cache hex})")
+        .help("Optional prefix of keys of caches, which may have '/'. ({key}/{project}/{lang}/{cache hex}{suffix})")
         .takes_value(true)
         .long("key")
         .short("k")
         .env("FAMO_KEY")
         .required(false)
+        .global(true)
+}
+
+fn arg_project<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("project")
+        .help("Optional name of the project in keys of caches, so that projects can share a bucket")
+        .takes_value(true)
+        .long("project")
+        .env("FAMO_PROJECT")
+        .global(true)
+}
+
+fn arg_key_suffix<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("key_suffix")
+        .help("Optional suffix appended to keys of caches (e.g. '.tar.gz')")
+        .takes_value(true)
+        .long("key_suffix")
+        .env("FAMO_KEY_SUFFIX")
+        .global(true)
+}
+
+fn arg_restore_key<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("restore_key")
+        .help("Prefix of keys whose latest cache is restored when the cache doesn't exist. (Tried in the order)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .long("restore_key")
+        .env("FAMO_RESTORE_KEYS")
+        .global(true)
+}
+
+fn arg_encryption_key<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("encryption_key")
+        .help("Key of AES-256-GCM encrypting caches (32 bytes in base64). The first key encrypts and all of them decrypt")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .long("encryption_key")
+        .env("FAMO_ENCRYPTION_KEY")
+        .hide_env_values(true)
+        .global(true)
+}
+
+fn arg_encryption_key_file<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("encryption_key_file")
+        .help("File of keys encrypting caches, one key per line (Following --encryption_key)")
+        .takes_value(true)
+        .long("encryption_key_file")
+        .env("FAMO_ENCRYPTION_KEY_FILE")
+        .global(true)
+}
+
+fn arg_compression<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("compression")
+        .help("Codec compressing caches. Caches are restored whichever codec compressed them")
+        .takes_value(true)
+        .long("compression")
+        .env("FAMO_COMPRESSION")
+        .possible_values(&["gzip", "zstd", "xz", "none"])
+        .default_value("gzip")
+        .global(true)
+}
+
+fn arg_compression_level<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("compression_level")
+        .help("Level of the codec (gzip: 0-9, zstd: 1-22, xz: 0-9)")
+        .takes_value(true)
+        .long("compression_level")
+        .env("FAMO_COMPRESSION_LEVEL")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_compression_threads<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("compression_threads")
+        .help("Number of threads compressing caches by zstd (All CPUs by default)")
+        .takes_value(true)
+        .long("compression_threads")
+        .env("FAMO_COMPRESSION_THREADS")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_preserve<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("preserve")
+        .help("Attributes of files preserved in caches (ownership is restored only by privileged users)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .long("preserve")
+        .env("FAMO_PRESERVE")
+        .possible_values(&["mtime", "permissions", "ownership", "xattrs"])
+        .default_value("mtime")
+        .global(true)
+}
+
+fn arg_normalize_mtime<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("normalize_mtime")
+        .help("Seconds since the epoch recorded as mtimes of all files, which makes caches reproducible")
+        .takes_value(true)
+        .long("normalize_mtime")
+        .env("FAMO_NORMALIZE_MTIME")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_max_unpacked_size<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("max_unpacked_size")
+        .help("Size in MiB of files unpacked from a cache at most. Larger caches are refused (32768 by default)")
+        .takes_value(true)
+        .long("max_unpacked_size")
+        .env("FAMO_MAX_UNPACKED_SIZE")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_max_entries<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("max_entries")
+        .help("Number of entries unpacked from a cache at most. Larger caches are refused (1000000 by default)")
+        .takes_value(true)
+        .long("max_entries")
+        .env("FAMO_MAX_ENTRIES")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_key_env<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("key_env")
+        .help("Environment variable whose value is included in the cache key (e.g. RUSTFLAGS)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .long("key_env")
+        .env("FAMO_KEY_ENV")
+        .global(true)
+}
+
+fn arg_key_probe<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("key_probe")
+        .help("Command whose output is included in the cache key (e.g. 'rustc -vV')")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .long("key_probe")
+        .env("FAMO_KEY_PROBE")
+        .global(true)
+}
+
+fn arg_addressing_style<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("addressing_style")
+        .help("How buckets are addressed. (path: {endpoint}/{bucket}, virtual: {bucket}.{endpoint})")
+        .takes_value(true)
+        .long("addressing_style")
+        .env("FAMO_ADDRESSING_STYLE")
+        .possible_values(&["path", "virtual"])
+        .default_value("path")
+        .global(true)
+}
+
+fn arg_signature_version<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("signature_version")
+        .help("Signature version for authenticating requests to S3. (v2 is for legacy compatibles)")
+        .takes_value(true)
+        .long("signature_version")
+        .env("FAMO_SIGNATURE_VERSION")
+        .possible_values(&["v2", "v4"])
+        .default_value("v4")
+        .global(true)
+}
+
+fn arg_server_side_encryption<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("server_side_encryption")
+        .help("Server-side encryption of caches uploaded to S3. (AES256: SSE-S3, aws:kms: SSE-KMS)")
+        .takes_value(true)
+        .long("server_side_encryption")
+        .env("FAMO_SERVER_SIDE_ENCRYPTION")
+        .possible_values(&["AES256", "aws:kms"])
+        .global(true)
+}
+
+fn arg_sse_kms_key_id<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("sse_kms_key_id")
+        .help("ID, ARN or alias of the KMS key for SSE-KMS (The default key of the account without it)")
+        .takes_value(true)
+        .long("sse_kms_key_id")
+        .env("FAMO_SSE_KMS_KEY_ID")
+        .global(true)
+}
+
+fn arg_sse_customer_key<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("sse_customer_key")
+        .help("Key of SSE-C (32 bytes in base64), which is required to restore caches")
+        .takes_value(true)
+        .long("sse_customer_key")
+        .env("FAMO_SSE_CUSTOMER_KEY")
+        .hide_env_values(true)
+        .validator(is_customer_key)
+        .conflicts_with_all(&["server_side_encryption", "sse_kms_key_id"])
+        .global(true)
+}
+
+// Classes of archives such as GLACIER are not supported, since their objects can't be read without restoring them.
+fn arg_storage_class<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("storage_class")
+        .help("Storage class of caches uploaded to S3")
+        .takes_value(true)
+        .long("storage_class")
+        .env("FAMO_STORAGE_CLASS")
+        .possible_values(&[
+            "STANDARD",
+            "REDUCED_REDUNDANCY",
+            "STANDARD_IA",
+            "ONEZONE_IA",
+            "INTELLIGENT_TIERING",
+            "GLACIER_IR",
+        ])
+        .global(true)
+}
+
+fn arg_tagging<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("tagging")
+        .help("Tag of caches uploaded to S3 (key=value)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .long("tagging")
+        .env("FAMO_TAGGING")
+        .validator(is_tag)
+        .global(true)
+}
+
+fn arg_acl<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("acl")
+        .help("Canned ACL of caches uploaded to S3")
+        .takes_value(true)
+        .long("acl")
+        .env("FAMO_ACL")
+        .possible_values(&[
+            "private",
+            "public-read",
+            "public-read-write",
+            "authenticated-read",
+            "aws-exec-read",
+            "bucket-owner-read",
+            "bucket-owner-full-control",
+        ])
+        .global(true)
+}
+
+fn arg_part_size<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("part_size")
+        .help("Size of each part in MiB. Larger archives are transferred in parts.")
+        .takes_value(true)
+        .long("part_size")
+        .env("FAMO_PART_SIZE")
+        .validator(is_number)
+        .global(true)
+}
+
+fn arg_concurrency<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("concurrency")
+        .help("Number of parts transferred at the same time")
+        .takes_value(true)
+        .long("concurrency")
+        .env("FAMO_CONCURRENCY")
+        .validator(is_number)
+        .global(true)
 }
 
 fn arg_archive<'a, 'b>() -> Arg<'a, 'b> {
@@ -134,14 +838,23 @@ fn arg_archive<'a, 'b>() -> Arg<'a, 'b> {
         .long("archive")
         .short("a")
         .env("FAMO_ARCHIVE")
+        .global(true)
 }
 
 fn arg_watch<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("watch")
-        .help("Paths of target files to be watched")
+        .help("Paths or glob patterns (e.g. '**/Cargo.toml') of target files to be watched")
         .multiple(true)
 }
 
+fn arg_run<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("run")
+        .help("Build command and its arguments (famo run -- cargo build)")
+        .multiple(true)
+        .last(true)
+        .required(true)
+}
+
 fn arg_command<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("command")
         .help("Build command of the project")
@@ -156,11 +869,127 @@ fn arg_verbose<'a, 'b>() -> Arg<'a, 'b> {
         .takes_value(false)
         .long("--verbose")
         .short("-v")
+        .global(true)
 }
 
 fn arg_async<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("async")
-        .help("[WIP] Uploading archive asyncronously. Use `famo wait` to sync the process.")
+        .help("Upload the archive by a background process. Use `famo wait` to wait for it.")
         .takes_value(false)
         .long("--async")
+        .global(true)
+}
+
+fn arg_cache_on_failure<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("cache_on_failure")
+        .help("Upload the archive even when the build command fails")
+        .takes_value(false)
+        .long("--cache_on_failure")
+        .global(true)
+}
+
+fn arg_symlinks<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("symlinks")
+        .help("How symbolic links in watched directories are hashed. (follow: contents of their targets, link: paths they point to)")
+        .takes_value(true)
+        .long("symlinks")
+        .env("FAMO_SYMLINKS")
+        .possible_values(&["follow", "link"])
+        .default_value("follow")
+        .global(true)
+}
+
+fn arg_watch_exclude<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("watch_exclude")
+        .help("Glob pattern of paths skipped in watched directories and patterns (e.g. '**/node_modules')")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .long("watch_exclude")
+        .env("FAMO_WATCH_EXCLUDE")
+        .global(true)
+}
+
+fn arg_watch_gitignore<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("watch_gitignore")
+        .help("Skip files ignored by .gitignore in watched directories and patterns")
+        .takes_value(false)
+        .long("--watch_gitignore")
+        .global(true)
+}
+
+fn arg_watch_missing<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("watch_missing")
+        .help("Fail or warn when a watched path or pattern matches nothing")
+        .takes_value(true)
+        .long("watch_missing")
+        .env("FAMO_WATCH_MISSING")
+        .possible_values(&["error", "warn"])
+        .default_value("error")
+        .global(true)
+}
+
+// Bytes of the size given in MiB, which is refused unless it fits in u64.
+fn mebibytes(matches: &ArgMatches, option: &str) -> Result<Option<u64>, Error> {
+    let size = match matches.value_of(option) {
+        Some(size) => size,
+        None => return Ok(None),
+    };
+
+    match size.parse::<u64>().ok().and_then(|size| size.checked_mul(1024 * 1024)) {
+        Some(bytes) => Ok(Some(bytes)),
+        None => Err(FamoError::MissedOption {
+            description: format!("{} MiB is too large. (--{})", size, option),
+        }.into()),
+    }
+}
+
+// Numeric options are validated by clap so that they never fail to be parsed later.
+fn is_number(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a number", value))
+}
+
+fn is_tag(value: String) -> Result<(), String> {
+    if value.is_empty() || value.starts_with('=') {
+        return Err(format!("'{}' has no key of the tag (key=value)", value));
+    }
+
+    Ok(())
+}
+
+fn is_customer_key(value: String) -> Result<(), String> {
+    if !s3_context::is_customer_key(&value) {
+        return Err("the key of SSE-C must be 32 bytes in base64".to_owned());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn quote_arguments_of_run() {
+        assert_eq!(quote("--release"), "--release");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn tags_in_env_are_delimited_by_commas() {
+        env::set_var("FAMO_TAGGING", "team=build,ttl=7");
+
+        let matches = App::new("famo")
+            .arg(arg_tagging())
+            .get_matches_from(vec!["famo"]);
+        let tags: Vec<&str> = matches.values_of("tagging").unwrap().collect();
+
+        assert_eq!(tags, vec!["team=build", "ttl=7"]);
+    }
 }
@@ -1,5 +1,50 @@
+use failure::Error;
+
+/// Exit code for invalid or missing options. (EX_USAGE of sysexits.h)
+pub const EXIT_CONFIG: i32 = 64;
+
+/// Exit code for backends which can't be set up or uploads in background which failed. (EX_UNAVAILABLE of sysexits.h)
+pub const EXIT_BACKEND: i32 = 69;
+
+/// Exit code for other errors. (EX_SOFTWARE of sysexits.h)
+pub const EXIT_INTERNAL: i32 = 70;
+
 #[derive(Debug, Fail)]
 pub enum FamoError {
     #[fail(display = "{}", description)]
     MissedOption { description: String },
+    #[fail(display = "Failed to set up {} backend: {}", backend, description)]
+    Backend {
+        backend: String,
+        description: String,
+    },
+    #[fail(display = "Upload in background failed ({})", reason)]
+    UploadFailed { reason: String },
+    #[fail(display = "`{}` failed ({})", command, status)]
+    BuildFailed {
+        command: String,
+        status: String,
+        code: i32,
+    },
+}
+
+impl FamoError {
+    ///
+    /// A failed build exits with the exit code of the build command as is (128 + the signal killing it),
+    /// so builds exiting with 64, 69 or 70 are told apart from errors of famo only by the messages.
+    ///
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FamoError::MissedOption { .. } => EXIT_CONFIG,
+            FamoError::Backend { .. } | FamoError::UploadFailed { .. } => EXIT_BACKEND,
+            FamoError::BuildFailed { code, .. } => *code,
+        }
+    }
+}
+
+pub fn exit_code(e: &Error) -> i32 {
+    match e.downcast_ref::<FamoError>() {
+        Some(e) => e.exit_code(),
+        None => EXIT_INTERNAL,
+    }
 }
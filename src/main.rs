@@ -1,3 +1,6 @@
+// `#[derive(Fail)]` expands into impls inside a named const.
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate clap;
 #[macro_use]
@@ -6,19 +9,28 @@ extern crate env_logger;
 #[macro_use]
 extern crate failure;
 extern crate famo_lib;
+extern crate tempfile;
 
+mod background;
 mod cli;
 mod error;
 mod phase;
+mod state;
 
 use failure::Error;
-use famo_lib::hash;
-use famo_lib::lang::detect;
-use famo_lib::s3;
+use clap::ArgMatches;
+use error::FamoError;
+use famo_lib::archive::{ArchiveError, Options as ArchiveOptions};
+use famo_lib::crypt::{CryptError, Key};
+use famo_lib::hash::{self, components};
+use famo_lib::lang::{detect, Lang};
+use famo_lib::storage::{Storage, StorageError};
+use state::State;
 use std::env;
+use std::process::{self, ExitStatus};
 
 fn main() {
-    if let Err(_) = env::var("RUST_LOG") {
+    if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "info");
     }
 
@@ -26,6 +38,7 @@ fn main() {
 
     if let Err(e) = main_inner() {
         error!("{:?}", e);
+        process::exit(error::exit_code(&e));
     }
 }
 
@@ -41,52 +54,301 @@ fn main_inner() -> Result<(), Error> {
         );
     }
 
-    let archive = cli::archive(&matches, &lang)?;
-    debug!("archive={}", archive);
+    if env::var_os(background::UPLOADER).is_some() {
+        let matches = match matches.subcommand() {
+            (_, Some(matches)) => matches,
+            _ => &matches,
+        };
 
-    let watches = cli::watches(&matches, &lang)?;
-    debug!("watches={:?}", watches);
+        return background::upload(matches);
+    }
+
+    match matches.subcommand() {
+        ("restore", Some(matches)) => restore(matches, &lang),
+        ("save", Some(matches)) => save(matches, &lang),
+        ("run", Some(matches)) => run(matches),
+        ("wait", Some(_)) => background::wait(),
+        _ => build(&matches, &lang),
+    }
+}
 
-    let command = cli::command(&matches, &lang)?;
+// Restore, build and save in one shot.
+fn build(matches: &ArgMatches, lang: &Option<Lang>) -> Result<(), Error> {
+    let archive = cli::archive(matches, lang)?;
+    debug!("archive={}", archive);
+
+    let command = cli::command(matches, lang)?;
     debug!("command={}", command);
 
-    let hex = hash::hex(&watches)?;
-    debug!("hex={}", hex);
+    let key = object_key(matches, lang)?;
+    let keys = cli::encryption_keys(matches)?;
+    let archive_options = cli::archive_options(matches)?;
 
     let verbose = matches.is_present("verbose");
     let async = matches.is_present("async");
+    let cache_on_failure = matches.is_present("cache_on_failure");
+
+    let storage = cli::storage(matches)?;
+    debug!("backend={}", storage.name());
+
+    background::clear()?;
+
+    let cache_exists = restore_cache(storage.as_ref(), &key, &archive_options, &keys, matches, lang);
+
+    let status = phase::execute_command(command, verbose)?;
+
+    if !cache_exists {
+        if !status.success() && !cache_on_failure {
+            warn!("The cache is not uploaded since the build failed. (--cache_on_failure uploads it)");
+        } else if async {
+            spawn_upload(&key, archive);
+        } else {
+            save_cache(storage.as_ref(), &key, archive, &archive_options, &keys);
+        }
+    }
+
+    check_status(command, status)
+}
+
+// `famo restore` records the key looked up, so that `famo save` stores the cache
+// under the same key even if the build changes the watched files.
+fn restore(matches: &ArgMatches, lang: &Option<Lang>) -> Result<(), Error> {
+    let key = object_key(matches, lang)?;
+    let keys = cli::encryption_keys(matches)?;
+    let archive_options = cli::archive_options(matches)?;
+
+    let storage = cli::storage(matches)?;
+    debug!("backend={}", storage.name());
+
+    background::clear()?;
+
+    let cache_exists = restore_cache(storage.as_ref(), &key, &archive_options, &keys, matches, lang);
+
+    let state = State::new(&".");
+    state.write("key", &key)?;
+    state.write("cached", &cache_exists.to_string())?;
+    state.remove("status")?;
 
-    let s3_context = s3::context::Context::from_matches(&matches);
-    debug!(
-        "endpoint={}, bucket={}, key={:?}",
-        s3_context.endpoint().to_owned(),
-        s3_context.bucket().to_owned(),
-        s3_context.key(),
-    );
+    Ok(())
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let command = cli::run(matches);
+    let status = phase::execute_command(&command, matches.is_present("verbose"))?;
+
+    State::new(&".").write("status", &exit_code(status).to_string())?;
+
+    check_status(&command, status)
+}
+
+fn save(matches: &ArgMatches, lang: &Option<Lang>) -> Result<(), Error> {
+    let state = State::new(&".");
+
+    if state.read("cached")? == Some("true".to_owned()) {
+        info!("The cache already exists. Nothing to save.");
+        return Ok(());
+    }
+
+    match state.read("status")? {
+        Some(ref status) if status != "0" && !matches.is_present("cache_on_failure") => {
+            warn!("The cache is not uploaded since the build failed. (--cache_on_failure uploads it)");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let archive = cli::archive(matches, lang)?;
+    debug!("archive={}", archive);
+
+    let keys = cli::encryption_keys(matches)?;
+    let archive_options = cli::archive_options(matches)?;
+
+    let key = match state.read("key")? {
+        Some(key) => key,
+        None => object_key(matches, lang)?,
+    };
+
+    if matches.is_present("async") {
+        spawn_upload(&key, archive);
+        return Ok(());
+    }
+
+    let storage = cli::storage(matches)?;
+    debug!("backend={}", storage.name());
+
+    if save_cache(storage.as_ref(), &key, archive, &archive_options, &keys) {
+        state.write("cached", "true")?;
+    }
+
+    Ok(())
+}
 
-    let cache_exists = match phase::download_if_cache_exists(&s3_context, &hex) {
-        Ok(exists) => exists,
+fn object_key(matches: &ArgMatches, lang: &Option<Lang>) -> Result<String, Error> {
+    let key = cli::object_key(matches, lang, &hex(matches, lang)?)?;
+    debug!("key={}", key);
+
+    Ok(key)
+}
+
+fn hex(matches: &ArgMatches, lang: &Option<Lang>) -> Result<String, Error> {
+    let watches = cli::watches(matches, lang)?;
+    debug!("watches={:?}", watches);
+
+    // Watches matching nothing are mistakes of options rather than failures of famo.
+    let mut leaves = hash::read(&watches, &cli::hash_options(matches)).map_err(|e| {
+        match e.downcast::<hash::HashError>() {
+            Ok(e @ hash::HashError::NoMatch { .. }) => FamoError::MissedOption {
+                description: e.to_string() + ". (--watch_missing=warn only warns it)",
+            }.into(),
+            Ok(e) => e.into(),
+            Err(e) => e,
+        }
+    })?;
+
+    // Caches depend on the platform and the toolchain as well as the watched files.
+    leaves.push(components::platform());
+
+    for name in cli::key_envs(matches, lang) {
+        leaves.push(components::env(name));
+    }
+
+    for command in cli::key_probes(matches, lang) {
+        leaves.push(components::probe(command)?);
+    }
+
+    debug!("platform={}", components::triple());
+
+    let hex = hash::hex_of(leaves);
+    debug!("hex={}", hex);
+
+    Ok(hex)
+}
+
+// Failures of downloading are not fatal since the build works without the cache.
+fn restore_cache(
+    storage: &dyn Storage,
+    key: &str,
+    options: &ArchiveOptions,
+    keys: &[Key],
+    matches: &ArgMatches,
+    lang: &Option<Lang>,
+) -> bool {
+    match phase::download_if_cache_exists(storage, key, options, keys) {
+        Ok(true) => true,
+        Ok(false) => {
+            if !restore_legacy_cache(storage, options, keys, matches, lang) {
+                restore_fallback_cache(storage, options, keys, matches);
+            }
+
+            false
+        }
         Err(e) => {
             warn!("{}", e);
-            warn!("Failed to download cache from S3.");
+
+            // Caches which are corrupted, can't be decrypted or are unsafe to unpack are bypassed
+            // and overwritten by the cache built this time.
+            if let Some(StorageError::Corrupted { .. }) = e.downcast_ref::<StorageError>() {
+                warn!("The corrupted cache is ignored and uploaded again after the building.");
+            } else if e.downcast_ref::<ArchiveError>().is_some() {
+                warn!("The cache which is unsafe to unpack is ignored and uploaded again after the building.");
+            } else if e.downcast_ref::<CryptError>().is_some() {
+                warn!("The cache which can't be decrypted is ignored and uploaded again after the building.");
+            } else {
+                warn!("Failed to download cache from {}.", storage.name());
+            }
+
             warn!("Continue for the building without cache...");
 
             false
         }
-    };
+    }
+}
 
-    phase::execute_command(&command, verbose)?;
+// Caches stored under keys of the legacy scheme are still restored,
+// but they are reported as missing so that the cache is stored again under the current key.
+fn restore_legacy_cache(
+    storage: &dyn Storage,
+    options: &ArchiveOptions,
+    keys: &[Key],
+    matches: &ArgMatches,
+    lang: &Option<Lang>,
+) -> bool {
+    let restored = cli::watches(matches, lang)
+        .and_then(|watches| hash::legacy::hex(&watches))
+        .and_then(|hex| phase::download_if_cache_exists(storage, &hex, options, keys));
 
-    if !cache_exists {
-        if async {
-            info!("Asyncronous mode. (This function is not working now.)");
+    match restored {
+        Ok(true) => {
+            info!("The cache of the legacy key is restored. It's uploaded again under the current key.");
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            warn!("Failed to restore the cache of the legacy key: {}", e);
+            false
+        }
+    }
+}
+
+// The first of restore keys which has caches restores the latest of them.
+// It may be outdated, so the cache of the current key is uploaded after the build.
+fn restore_fallback_cache(storage: &dyn Storage, options: &ArchiveOptions, keys: &[Key], matches: &ArgMatches) {
+    for prefix in cli::restore_keys(matches) {
+        match phase::download_latest(storage, prefix, options, keys) {
+            Ok(Some(key)) => {
+                info!("The cache is partially restored from {}. It's uploaded again under the current key.", key);
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to restore the cache of '{}': {}", prefix, e),
         }
+    }
+}
 
-        if let Err(e) = phase::upload_archive(&s3_context, &hex, &archive) {
+// Failures of uploading are not fatal since the build itself has succeeded.
+fn save_cache(storage: &dyn Storage, key: &str, archive: &str, options: &ArchiveOptions, keys: &[Key]) -> bool {
+    match phase::upload_archive(storage, key, archive, options, keys) {
+        Ok(()) => true,
+        Err(e) => {
             warn!("{}", e);
-            warn!("Failed to upload cache to S3.");
+            warn!("Failed to upload cache to {}.", storage.name());
+
+            false
         }
     }
+}
+
+fn spawn_upload(key: &str, archive: &str) {
+    if let Err(e) = background::spawn(key, archive) {
+        warn!("{}", e);
+        warn!("Failed to start uploading cache in background.");
+    }
+}
+
+// A command killed by a signal has no exit code, and exits with 128 + the signal as shells report.
+#[cfg(unix)]
+fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(error::EXIT_INTERNAL)
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(error::EXIT_INTERNAL)
+}
+
+fn check_status(command: &str, status: ExitStatus) -> Result<(), Error> {
+    if !status.success() {
+        return Err(FamoError::BuildFailed {
+            command: command.to_owned(),
+            status: status.to_string(),
+            code: exit_code(status),
+        }.into());
+    }
 
     Ok(())
 }
@@ -1,33 +1,63 @@
 use failure::Error;
-use famo_lib::archive;
-use famo_lib::s3;
-use famo_lib::s3::context::Context as S3Context;
-use std::process::{Command, Stdio};
+use famo_lib::archive::{self, Options as ArchiveOptions};
+use famo_lib::crypt::{self, Encryptor, Key};
+use famo_lib::storage::{checksum, Metadata, Storage};
+use std::io::{self, Read, Seek};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 
-pub fn download_if_cache_exists(s3_context: &S3Context, hex: &str) -> Result<bool, Error> {
-    if s3::key_exists(&s3_context, &hex)? {
-        info!("The cache exists on S3.");
-
-        info!("--- Downloading");
-        let bytes = s3::download(&s3_context, &hex)?;
-        info!("--- ---> Done ({} bytes)", bytes.len());
-
-        info!("--- Decoding");
-        let bytes = archive::decode(bytes.as_slice(), Vec::new())?;
-        info!("--- ---> Done ({} bytes)", bytes.len());
-
-        info!("--- Unpacking");
-        archive::unpack(bytes.as_slice(), &".")?;
-        info!("--- ---> Done");
+pub fn download_if_cache_exists(
+    storage: &dyn Storage,
+    key: &str,
+    options: &ArchiveOptions,
+    keys: &[Key],
+) -> Result<bool, Error> {
+    if storage.exists(key)? {
+        info!("The cache exists on {}.", storage.name());
+        download(storage, key, options, keys)?;
 
         Ok(true)
     } else {
-        info!("The cache doesn't exist on S3.");
+        info!("The cache doesn't exist on {}.", storage.name());
         Ok(false)
     }
 }
 
-pub fn execute_command(command: &str, verbose: bool) -> Result<(), Error> {
+///
+/// Download the most recent cache whose key starts with the prefix.
+/// Returns the key of the cache if it's found.
+///
+pub fn download_latest(
+    storage: &dyn Storage,
+    prefix: &str,
+    options: &ArchiveOptions,
+    keys: &[Key],
+) -> Result<Option<String>, Error> {
+    match storage.latest(prefix)? {
+        Some(entry) => {
+            info!("The latest cache of '{}' is {} on {}.", prefix, entry.key(), storage.name());
+            download(storage, entry.key(), options, keys)?;
+
+            Ok(Some(entry.key().to_owned()))
+        }
+        None => {
+            info!("No cache of '{}' exists on {}.", prefix, storage.name());
+            Ok(None)
+        }
+    }
+}
+
+// Caches are decrypted by the keys if they're given, and files are restored with the attributes of the options.
+fn download(storage: &dyn Storage, key: &str, options: &ArchiveOptions, keys: &[Key]) -> Result<(), Error> {
+    info!("--- Downloading, decoding and unpacking");
+    let reader = crypt::decrypt(storage.get(key)?, keys)?;
+    archive::unpack_encoded_with(reader, &".", options)?;
+    info!("--- ---> Done");
+
+    Ok(())
+}
+
+pub fn execute_command(command: &str, verbose: bool) -> Result<ExitStatus, Error> {
     info!("Execute `{}`", command);
 
     let stdout = if verbose {
@@ -36,31 +66,117 @@ pub fn execute_command(command: &str, verbose: bool) -> Result<(), Error> {
         Stdio::null()
     };
 
-    Command::new("sh")
+    // Errors of the command are always shown, since they explain why the build failed.
+    let status = Command::new("sh")
         .arg("-c")
         .arg(command)
         .stdout(stdout)
-        .output()?;
+        .stderr(Stdio::inherit())
+        .status()?;
 
-    Ok(())
+    Ok(status)
+}
+
+pub fn upload_archive(
+    storage: &dyn Storage,
+    key: &str,
+    archive: &str,
+    options: &ArchiveOptions,
+    keys: &[Key],
+) -> Result<(), Error> {
+    upload_archive_with_progress(storage, key, archive, options, keys, Box::new(|_, _| {}))
 }
 
-pub fn upload_archive(s3_context: &S3Context, hex: &str, archive: &str) -> Result<(), Error> {
-    let tarball = {
-        info!("--- Archiving");
-        let bytes = archive::pack_dir(&archive, Vec::new())?;
-        info!("--- ---> Done ({} bytes)", bytes.len());
+///
+/// Called with the number of bytes uploaded so far and the length of the archive.
+///
+pub type Progress = Box<dyn FnMut(u64, u64) + Send>;
 
-        info!("--- Encoding");
-        let bytes = archive::encode(bytes.as_slice(), Vec::new())?;
-        info!("--- ---> Done ({} bytes)", bytes.len());
+///
+/// The archive is compressed by the codec of the options recording the attributes of files, and the first of the keys encrypts it if they're given.
+///
+pub fn upload_archive_with_progress(
+    storage: &dyn Storage,
+    key: &str,
+    archive: &str,
+    options: &ArchiveOptions,
+    keys: &[Key],
+    progress: Progress,
+) -> Result<(), Error> {
+    let mut metadata = Metadata::default();
 
-        bytes
+    // The tarball is spooled to an anonymous temporary file instead of memory,
+    // since backends such as S3 require the length of the object before uploading it.
+    // The checksum is stored with the cache and verified before it's unpacked.
+    let (tarball, len) = {
+        info!("--- Archiving and encoding by {}", options.codec);
+        let mut file = tempfile::tempfile()?;
+
+        match keys.first() {
+            Some(encryption_key) => {
+                info!("--- Encrypting by the key {}", encryption_key.id());
+                archive::pack_dir_with(&archive, Encryptor::new(&mut file, encryption_key)?, options)?
+                    .finish()?;
+                metadata.set_key_id(Some(encryption_key.id().to_owned()));
+            }
+            None => {
+                archive::pack_dir_with(&archive, &mut file, options)?;
+            }
+        }
+
+        let len = file.stream_position()?;
+        file.rewind()?;
+        let sha256 = checksum::sha256_hex(&mut file)?;
+        file.rewind()?;
+        info!("--- ---> Done ({} bytes, SHA-256 {})", len, sha256);
+
+        metadata.set_sha256(Some(sha256));
+        (file, len)
     };
 
     info!("--- Uploading");
-    s3::upload(&s3_context, &hex, tarball.to_vec())?;
+    let reader = Box::new(ProgressReader::new(tarball, len, progress));
+    storage.put_with_metadata(key, reader, len, &metadata)?;
     info!("--- ---> Done!");
 
     Ok(())
 }
+
+// Report the progress of reading at most once per second.
+struct ProgressReader<R> {
+    inner: R,
+    read: u64,
+    len: u64,
+    progress: Progress,
+    reported: Option<Instant>,
+}
+
+impl<R: Read> ProgressReader<R> {
+    fn new(inner: R, len: u64, progress: Progress) -> Self {
+        ProgressReader {
+            inner,
+            read: 0,
+            len,
+            progress,
+            reported: None,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+
+        let due = self
+            .reported
+            .is_none_or(|reported| reported.elapsed() >= Duration::from_secs(1));
+
+        if due || n == 0 {
+            (self.progress)(self.read, self.len);
+            self.reported = Some(Instant::now());
+        }
+
+        Ok(n)
+    }
+}
@@ -0,0 +1,55 @@
+use failure::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Directory of the state under the working directory.
+pub const STATE_DIR: &str = ".famo";
+
+///
+/// State shared between subcommands which run as separate steps of a job.
+/// Each value is stored as a file named by its name.
+///
+pub struct State {
+    dir: PathBuf,
+}
+
+impl State {
+    pub fn new(dir: &dyn AsRef<Path>) -> Self {
+        State {
+            dir: dir.as_ref().join(STATE_DIR),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn read(&self, name: &str) -> Result<Option<String>, Error> {
+        match fs::read_to_string(self.dir.join(name)) {
+            Ok(value) => Ok(Some(value.trim().to_owned())),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Values are replaced by renaming, so that readers never see partially written ones.
+    pub fn write(&self, name: &str, value: &str) -> Result<(), Error> {
+        let partial = self.dir.join(format!("{}.{}.partial", name, process::id()));
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&partial, value)?;
+        fs::rename(&partial, self.dir.join(name))?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.dir.join(name)) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+extern crate filetime;
+extern crate tempfile;
+
+mod common;
+
+use common::{build, project};
+use filetime::FileTime;
+use std::fs;
+use std::path::Path;
+
+fn mtime(path: &Path) -> FileTime {
+    FileTime::from_last_modification_time(&fs::metadata(path).unwrap())
+}
+
+#[test]
+fn mtimes_of_artifacts_are_restored_in_nanoseconds() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+    let recorded = FileTime::from_unix_time(1_546_300_800, 123_456_789);
+    let build = |args: &[&str]| {
+        let mut all = vec!["--command=true"];
+        all.extend(args);
+
+        build(dir.path(), &all)
+    };
+
+    filetime::set_file_times(&artifact, recorded, recorded).unwrap();
+    assert_eq!(build(&[]), 0);
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build(&[]), 0);
+    assert_eq!(mtime(&artifact), recorded);
+
+    // Mtimes are left to the time of unpacking unless they're preserved.
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build(&["--preserve=permissions"]), 0);
+    assert_ne!(mtime(&artifact), recorded);
+}
+
+#[test]
+fn normalized_mtimes_are_recorded() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+
+    assert_eq!(
+        build(dir.path(), &["--command=true", "--normalize_mtime=0"]),
+        0
+    );
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build(dir.path(), &["--command=true"]), 0);
+    assert_eq!(mtime(&artifact), FileTime::from_unix_time(0, 0));
+}
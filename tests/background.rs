@@ -0,0 +1,60 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{cached, famo, project};
+
+#[test]
+fn wait_for_upload_in_background() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--async", "--archive=target", "--command=true", "watched"]
+        ),
+        0
+    );
+    assert_eq!(famo(dir.path(), &["wait"]), 0);
+    assert!(cached(dir.path()));
+
+    // The result is kept for later `famo wait`.
+    assert_eq!(famo(dir.path(), &["wait"]), 0);
+}
+
+#[test]
+fn wait_fails_when_upload_in_background_fails() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--async",
+                "--local_dir=watched/cache",
+                "--archive=target",
+                "--command=true",
+                "watched"
+            ]
+        ),
+        0
+    );
+    assert_eq!(famo(dir.path(), &["wait"]), 69);
+}
+
+#[test]
+fn save_in_background() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["restore", "watched"]), 0);
+    assert_eq!(famo(dir.path(), &["save", "--async", "--archive=target"]), 0);
+    assert_eq!(famo(dir.path(), &["wait"]), 0);
+    assert!(cached(dir.path()));
+}
+
+#[test]
+fn wait_without_upload() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["wait"]), 0);
+}
@@ -0,0 +1,152 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{famo_warnings, project};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Bodies and metadata (`x-amz-meta-*` headers) of objects by their paths.
+type Objects = Arc<Mutex<BTreeMap<String, (Vec<u8>, Vec<String>)>>>;
+
+// A stand-in of S3 which keeps objects in memory and answers requests famo sends without verifying them.
+fn stand_in() -> (String, Objects) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let objects: Objects = Arc::default();
+    let shared = objects.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let objects = shared.clone();
+            thread::spawn(move || serve(stream.unwrap(), &objects));
+        }
+    });
+
+    (url, objects)
+}
+
+fn serve(stream: TcpStream, objects: &Objects) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap().to_owned();
+        let target = parts.next().unwrap().to_owned();
+        let (path, query) = match target.find('?') {
+            Some(i) => (target[..i].to_owned(), target[i + 1..].to_owned()),
+            None => (target.clone(), String::new()),
+        };
+        let mut metadata = vec![];
+        let mut len = 0;
+
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+
+            if header.trim().is_empty() {
+                break;
+            }
+
+            let lower = header.to_lowercase();
+
+            if lower.starts_with("x-amz-meta-") {
+                metadata.push(header.trim().to_owned());
+            } else if let Some(value) = lower.strip_prefix("content-length:") {
+                len = value.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut objects = objects.lock().unwrap();
+        let object = objects.get(&path).cloned();
+
+        let (status, headers, content) = match (method.as_str(), query.as_str(), object) {
+            ("GET", "acl", Some(_)) => ("200 OK", vec![], b"<AccessControlPolicy/>".to_vec()),
+            ("GET", query, _) if query.starts_with("list-type=2") => {
+                ("200 OK", vec![], b"<ListBucketResult/>".to_vec())
+            }
+            ("HEAD", _, Some((body, metadata))) | ("GET", "", Some((body, metadata))) => {
+                ("200 OK", metadata, body)
+            }
+            ("PUT", _, _) => {
+                objects.insert(path, (body, metadata));
+                ("200 OK", vec!["ETag: \"etag\"".to_owned()], vec![])
+            }
+            _ => ("404 Not Found", vec![], b"<Error/>".to_vec()),
+        };
+
+        write!(stream, "HTTP/1.1 {}\r\n", status).unwrap();
+
+        for header in headers {
+            write!(stream, "{}\r\n", header).unwrap();
+        }
+
+        write!(stream, "Content-Length: {}\r\n\r\n", content.len()).unwrap();
+
+        if method != "HEAD" {
+            stream.write_all(&content).unwrap();
+        }
+    }
+}
+
+#[test]
+fn tampered_caches_are_bypassed_and_uploaded_again() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+    let (url, objects) = stand_in();
+    let endpoint = format!("--endpoint={}", url);
+    let build = |command: &str| {
+        famo_warnings(
+            dir.path(),
+            &[
+                "--backend=s3",
+                "--bucket=famo",
+                &endpoint,
+                "--region=us-east-1",
+                "--access_key_id=AKIAEXAMPLE",
+                "--secret_access_key=secret",
+                "--archive=target",
+                command,
+                "--key_probe=true",
+                "watched",
+            ],
+        )
+    };
+
+    assert_eq!(build("--command=true").0, 0);
+    assert_eq!(objects.lock().unwrap().len(), 1);
+
+    // The body is changed while its SHA-256 in the metadata is kept.
+    for (body, metadata) in objects.lock().unwrap().values_mut() {
+        assert!(metadata
+            .iter()
+            .any(|header| header.starts_with("x-amz-meta-famo-sha256")));
+
+        let last = body.len() - 1;
+        body[last] ^= 0xff;
+    }
+
+    fs::remove_file(&artifact).unwrap();
+    let (code, warnings) = build("--command=echo rebuilt > target/artifact");
+    assert_eq!(code, 0);
+    assert!(warnings.contains("The corrupted cache is ignored"));
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build("--command=true").0, 0);
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+}
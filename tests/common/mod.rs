@@ -0,0 +1,94 @@
+// Helpers shared by tests which invoke the binary.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile;
+
+// Run famo in the directory with the local backend by default, so that no network is required.
+pub fn famo(dir: &Path, args: &[&str]) -> i32 {
+    let status = command(dir, args)
+        .env("RUST_LOG", "off")
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    status.code().unwrap()
+}
+
+// Run famo as `famo` does, and return the exit code with warnings written to stderr.
+pub fn famo_warnings(dir: &Path, args: &[&str]) -> (i32, String) {
+    let output = command(dir, args).env("RUST_LOG", "warn").output().unwrap();
+
+    (
+        output.status.code().unwrap(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// Build `target` watching `watched` with the options such as `--command`.
+pub fn build(dir: &Path, args: &[&str]) -> i32 {
+    let mut all = vec!["--archive=target"];
+    all.extend(args);
+    all.push("watched");
+
+    famo(dir, &all)
+}
+
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_famo"));
+    command
+        .current_dir(dir)
+        .env("FAMO_BACKEND", "local")
+        .env("FAMO_LOCAL_DIR", dir.join("cache"))
+        .args(args);
+
+    command
+}
+
+pub fn project() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+
+    fs::create_dir(dir.path().join("target")).unwrap();
+    fs::write(dir.path().join("target").join("artifact"), "artifact").unwrap();
+    fs::write(dir.path().join("watched"), "watched").unwrap();
+
+    dir
+}
+
+// Caches stored in the local backend, leaving out the directory of files being written.
+pub fn caches(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir.join("cache"))
+        .map(|entries| {
+            entries
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| !path.ends_with(".famo-partial"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn cached(dir: &Path) -> bool {
+    !caches(dir).is_empty()
+}
+
+// The only cache stored in the local backend.
+pub fn cache(dir: &Path) -> PathBuf {
+    caches(dir).into_iter().next().unwrap()
+}
+
+// A tarball planted by others, whose path can't be written by `Header::set_path`.
+pub fn planted(path: &str) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+
+    header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+    header.set_mode(0o644);
+    header.set_size(4);
+    header.set_cksum();
+    builder.append(&header, io::repeat(b'!').take(4)).unwrap();
+
+    builder.into_inner().unwrap()
+}
@@ -0,0 +1,42 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{build, cache, project};
+use std::fs;
+
+#[test]
+fn caches_are_restored_whichever_codec_compressed_them() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+
+    assert_eq!(
+        build(dir.path(), &["--command=true", "--compression=zstd"]),
+        0
+    );
+    assert!(fs::read(cache(dir.path()))
+        .unwrap()
+        .starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(
+        build(dir.path(), &["--command=true", "--compression=xz"]),
+        0
+    );
+    assert!(artifact.exists());
+}
+
+#[test]
+fn invalid_levels_exit_with_config_error() {
+    let dir = project();
+    let invalid = |args: &[&str]| {
+        let mut all = vec!["--command=true", "--compression=zstd"];
+        all.extend(args);
+
+        build(dir.path(), &all)
+    };
+
+    assert_eq!(invalid(&["--compression_level=23"]), 64);
+    assert_eq!(invalid(&["--compression_level=4294967296"]), 64);
+    assert_eq!(invalid(&["--compression_threads=4294967296"]), 64);
+}
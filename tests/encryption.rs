@@ -0,0 +1,50 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{build, cache, project};
+use std::fs;
+
+const KEY1: &str = "--encryption_key=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+const KEY2: &str = "--encryption_key=AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+
+#[test]
+fn caches_are_encrypted_and_keys_can_be_rotated() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+
+    assert_eq!(build(dir.path(), &["--command=true", KEY1]), 0);
+    assert!(fs::read(cache(dir.path()))
+        .unwrap()
+        .starts_with(b"FAMOENC1"));
+
+    // The new key is prepended, and the old one still decrypts the cache.
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build(dir.path(), &["--command=true", KEY2, KEY1]), 0);
+    assert!(artifact.exists());
+
+    // The cache which can't be decrypted is bypassed and uploaded again by the new key.
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(
+        build(
+            dir.path(),
+            &["--command=echo rebuilt > target/artifact", KEY2]
+        ),
+        0
+    );
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build(dir.path(), &["--command=true", KEY2]), 0);
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+}
+
+#[test]
+fn invalid_keys_exit_with_config_error() {
+    let dir = project();
+
+    assert_eq!(
+        build(dir.path(), &["--command=true", "--encryption_key=short"]),
+        64
+    );
+}
@@ -0,0 +1,166 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{cached, famo, project};
+
+#[test]
+fn successful_build_exits_zero() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--archive=target", "--command=true", "watched"]
+        ),
+        0
+    );
+    assert!(cached(dir.path()));
+}
+
+#[test]
+fn failed_build_exits_with_its_code() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--archive=target", "--command=exit 7", "watched"]
+        ),
+        7
+    );
+    assert!(!cached(dir.path()));
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--archive=target",
+                "--command=exit 7",
+                "--cache_on_failure",
+                "watched"
+            ]
+        ),
+        7
+    );
+    assert!(cached(dir.path()));
+}
+
+#[cfg(unix)]
+#[test]
+fn killed_build_exits_with_128_plus_signal() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--archive=target", "--command=kill -KILL $$", "watched"]
+        ),
+        137
+    );
+    assert!(!cached(dir.path()));
+}
+
+#[test]
+fn missing_options_exit_with_config_error() {
+    let dir = tempfile::tempdir().unwrap();
+
+    assert_eq!(famo(dir.path(), &["--command=true", "watched"]), 64);
+}
+
+#[test]
+fn invalid_options_exit_with_config_error() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["--concurrency=many", "watched"]), 64);
+    assert_eq!(famo(dir.path(), &["--backend=floppy", "watched"]), 64);
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--archive=target",
+                "--command=true",
+                "--access_key_id=AKIAEXAMPLE",
+                "watched"
+            ]
+        ),
+        64
+    );
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--backend=s3",
+                "--bucket=famo",
+                "--endpoint=http://127.0.0.1:1",
+                "--region=us-east-1",
+                "--sse_customer_key=short",
+                "--archive=target",
+                "--command=true",
+                "watched"
+            ]
+        ),
+        64
+    );
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--backend=s3",
+                "--bucket=famo",
+                "--endpoint=http://127.0.0.1:1",
+                "--region=us-east-1",
+                "--part_size=99999999999999999",
+                "--archive=target",
+                "--command=true",
+                "watched"
+            ]
+        ),
+        64
+    );
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--backend=redis",
+                "--redis_url=redis://127.0.0.1:1",
+                "--redis_chunk_size=99999999999999999",
+                "--archive=target",
+                "--command=true",
+                "watched"
+            ]
+        ),
+        64
+    );
+}
+
+#[test]
+fn unavailable_backend_exits_with_backend_error() {
+    let dir = project();
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--backend=redis",
+                "--redis_url=not a url",
+                "--archive=target",
+                "--command=true",
+                "watched"
+            ]
+        ),
+        69
+    );
+}
+
+#[test]
+fn unmatched_watch_exits_with_config_error() {
+    let dir = project();
+    let args = ["--archive=target", "--command=true", "watched", "**/*.lock"];
+
+    assert_eq!(famo(dir.path(), &args), 64);
+    assert!(!cached(dir.path()));
+
+    assert_eq!(famo(dir.path(), &[&["--watch_missing=warn"], &args[..]].concat()), 0);
+    assert!(cached(dir.path()));
+}
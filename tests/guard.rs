@@ -0,0 +1,52 @@
+extern crate tar;
+extern crate tempfile;
+
+mod common;
+
+use common::{build, cache, planted, project};
+use std::fs;
+
+#[test]
+fn planted_caches_are_refused_and_uploaded_again() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+    let build = |command: &str| build(dir.path(), &[command, "--compression=none"]);
+
+    assert_eq!(build("--command=true"), 0);
+
+    let escaped = format!(
+        "{}-escaped",
+        dir.path().file_name().unwrap().to_string_lossy()
+    );
+    fs::write(cache(dir.path()), planted(&format!("../{}", escaped))).unwrap();
+
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build("--command=echo rebuilt > target/artifact"), 0);
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+    assert!(!dir.path().parent().unwrap().join(&escaped).exists());
+
+    // Nothing is left by unpacking the refused cache.
+    let names: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(!names.iter().any(|name| name.starts_with(".famo-unpacking")));
+
+    // The cache is uploaded again by the build.
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(build("--command=true"), 0);
+    assert_eq!(fs::read_to_string(&artifact).unwrap().trim(), "rebuilt");
+}
+
+#[test]
+fn overflowing_limits_exit_with_config_error() {
+    let dir = project();
+
+    assert_eq!(
+        build(
+            dir.path(),
+            &["--command=true", "--max_unpacked_size=18446744073709551615"]
+        ),
+        64
+    );
+}
@@ -0,0 +1,98 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{caches, famo, project};
+use std::fs;
+
+#[test]
+fn toolchain_probes_are_included_in_key() {
+    let dir = project();
+    let args = ["--archive=target", "--command=true", "--key_probe=echo 1.30", "watched"];
+
+    assert_eq!(famo(dir.path(), &args), 0);
+    assert_eq!(famo(dir.path(), &args), 0);
+    assert_eq!(caches(dir.path()).len(), 1);
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--archive=target", "--command=true", "--key_probe=echo 1.31", "watched"]
+        ),
+        0
+    );
+    assert_eq!(caches(dir.path()).len(), 2);
+}
+
+#[test]
+fn restore_keys_restore_latest_cache_of_prefix() {
+    let dir = project();
+    let artifact = dir.path().join("target").join("artifact");
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["--archive=target", "--command=true", "--key_probe=echo 1", "watched"]
+        ),
+        0
+    );
+
+    // The failed build isn't cached, so that the cache of the first build stays the latest.
+    fs::remove_file(&artifact).unwrap();
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--archive=target",
+                "--command=exit 1",
+                "--key_probe=echo 2",
+                "--restore_key=missing/",
+                "watched"
+            ]
+        ),
+        1
+    );
+    assert!(!artifact.exists());
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &[
+                "--archive=target",
+                "--command=true",
+                "--key_probe=echo 2",
+                "--restore_key=missing/",
+                "--restore_key=v1-",
+                "watched"
+            ]
+        ),
+        0
+    );
+    assert!(artifact.exists());
+    assert_eq!(caches(dir.path()).len(), 2);
+}
+
+#[test]
+fn caches_are_stored_under_prefix_and_project() {
+    let dir = project();
+    let args = [
+        "--archive=target",
+        "--command=true",
+        "--key=team/app",
+        "--project=web",
+        "--key_suffix=.tar.gz",
+        "watched",
+    ];
+
+    assert_eq!(famo(dir.path(), &args), 0);
+
+    let caches: Vec<String> = fs::read_dir(dir.path().join("cache/team/app/web"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+
+    assert_eq!(caches.len(), 1);
+    assert!(caches[0].starts_with("v1-") && caches[0].ends_with(".tar.gz"));
+
+    assert_eq!(famo(dir.path(), &["--project=..", "--archive=target", "watched"]), 64);
+}
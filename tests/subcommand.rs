@@ -0,0 +1,56 @@
+extern crate tempfile;
+
+mod common;
+
+use common::{cached, famo, project};
+use std::fs;
+
+#[test]
+fn restore_run_and_save() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["restore", "watched"]), 0);
+    assert!(!cached(dir.path()));
+
+    assert_eq!(famo(dir.path(), &["run", "--", "touch", "built file"]), 0);
+    assert!(dir.path().join("built file").exists());
+
+    // The key looked up by `restore` is used even if the build changes the watched files.
+    fs::write(dir.path().join("watched"), "changed").unwrap();
+
+    assert_eq!(famo(dir.path(), &["save", "--archive=target"]), 0);
+    assert!(cached(dir.path()));
+
+    fs::write(dir.path().join("watched"), "watched").unwrap();
+    fs::remove_dir_all(dir.path().join("target")).unwrap();
+
+    assert_eq!(famo(dir.path(), &["restore", "watched"]), 0);
+    assert!(dir.path().join("target").join("artifact").exists());
+}
+
+#[test]
+fn save_skips_failed_builds() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["restore", "watched"]), 0);
+    assert_eq!(famo(dir.path(), &["run", "--", "sh", "-c", "exit 3"]), 3);
+
+    assert_eq!(famo(dir.path(), &["save", "--archive=target"]), 0);
+    assert!(!cached(dir.path()));
+
+    assert_eq!(
+        famo(
+            dir.path(),
+            &["save", "--archive=target", "--cache_on_failure"]
+        ),
+        0
+    );
+    assert!(cached(dir.path()));
+}
+
+#[test]
+fn run_requires_command() {
+    let dir = project();
+
+    assert_eq!(famo(dir.path(), &["run"]), 64);
+}